@@ -0,0 +1,329 @@
+//! What the device does when its buttons are pressed, apart from the hardware: the
+//! firmware feeds in button presses and carries out the LCD and USB commands that
+//! come back.
+//!
+//! The device starts locked, with a sliding window over the last few buttons pressed
+//! as the unlock code. Once a code opens the vault, the entries are decrypted and kept
+//! until reset, and the buttons lock the device, cycle through the entries, and type
+//! the selected one. Unlocking again only has to verify the code. If the entries can't
+//! be decrypted, or the vault is older than one the device has opened before, the
+//! device shows that it has no vault and ignores the buttons.
+
+#![no_std]
+
+#[cfg(test)]
+mod tests;
+
+use etpwtc::{heapless, EndecError, Entry, Kdf, Key, LoadError, Plaintext, StoredVault};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+}
+
+impl Button {
+    /// The character the button enters into an unlock code.
+    pub fn code(self) -> u8 {
+        match self {
+            Button::A => b'a',
+            Button::B => b'b',
+            Button::X => b'x',
+            Button::Y => b'y',
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LcdMessage {
+    Lock,
+    Wake,
+    Unlock,
+    SetName([u8; 4]),
+    /// There's no usable vault in flash, so there's nothing to unlock.
+    NoVault(VaultProblem),
+}
+
+/// Why there's no vault to unlock, for the LCD to show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VaultProblem {
+    /// Nothing has been written to the VAULT region.
+    Empty,
+    /// The VAULT region couldn't be read.
+    Flash,
+    /// The vault has more entries than the firmware has room for.
+    TooManyEntries,
+    /// The VAULT region holds something other than a vault image this firmware can read.
+    BadImage,
+    /// A code opened the vault, but its entries failed their integrity check or
+    /// wouldn't decrypt.
+    Damaged,
+    /// The vault's version is lower than one the device has already opened, so it
+    /// may have been flashed back over a newer one.
+    Rollback,
+}
+
+impl VaultProblem {
+    /// A label of up to 11 characters, to fit across the LCD.
+    pub fn label(self) -> &'static str {
+        match self {
+            VaultProblem::Empty => "EMPTY",
+            VaultProblem::Flash => "FLASH ERROR",
+            VaultProblem::TooManyEntries => "TOO MANY",
+            VaultProblem::BadImage => "BAD IMAGE",
+            VaultProblem::Damaged => "DAMAGED",
+            VaultProblem::Rollback => "ROLLBACK",
+        }
+    }
+}
+
+impl<E> From<&LoadError<E>> for VaultProblem {
+    fn from(error: &LoadError<E>) -> Self {
+        match error {
+            LoadError::Empty => VaultProblem::Empty,
+            LoadError::Storage(_) => VaultProblem::Flash,
+            LoadError::Invalid(EndecError::TooManyEntries) => VaultProblem::TooManyEntries,
+            LoadError::Invalid(_) => VaultProblem::BadImage,
+        }
+    }
+}
+
+/// Text for the USB keyboard to type.
+#[derive(Debug)]
+pub enum UsbMessage<const N: usize> {
+    /// The username, then tab, the password and enter, to fill in a login form.
+    Credentials {
+        username: Plaintext<N>,
+        password: Plaintext<N>,
+    },
+    /// The password, then enter.
+    Password { password: Plaintext<N> },
+}
+
+#[derive(Debug)]
+pub enum Command<const N: usize> {
+    Lcd(LcdMessage),
+    Usb(UsbMessage<N>),
+    /// The vault opened with a higher version than the minimum the app was started
+    /// with. The firmware keeps it as the new minimum, for the app to refuse older
+    /// vaults with after reset.
+    KeepVersion(u32),
+}
+
+/// The commands for one button press, to carry out in order.
+pub type Commands<const N: usize> = heapless::Vec<Command<N>, 3>;
+
+/// The device's state, for a vault of up to `MAX` entries of up to `N` bytes, opened
+/// with codes of `CODE_LENGTH` buttons.
+pub struct App<const N: usize, const MAX: usize, const CODE_LENGTH: usize> {
+    stored: StoredVault<N, MAX>,
+    /// The lowest version of vault that can be opened.
+    minimum_version: u32,
+    code: CodeWindow<CODE_LENGTH>,
+    state: State<N, MAX>,
+    selected: usize,
+}
+
+enum State<const N: usize, const MAX: usize> {
+    /// No code has opened the vault since reset.
+    Sealed,
+    /// The entries, decrypted once the vault is first opened and then kept until reset.
+    Opened {
+        entries: heapless::Vec<Plaintext<N>, MAX>,
+        unlocked: bool,
+    },
+    /// A code opened the vault, but its entries failed their integrity check or
+    /// wouldn't decrypt, or it was too old, so there's nothing to show until it's
+    /// reflashed.
+    Unusable,
+}
+
+impl<const N: usize, const MAX: usize, const CODE_LENGTH: usize> App<N, MAX, CODE_LENGTH> {
+    /// An app for the vault in `stored`, which opens only if its version is at least
+    /// `minimum_version`, as kept from the last `Command::KeepVersion`.
+    pub fn new(stored: StoredVault<N, MAX>, minimum_version: u32) -> Self {
+        App {
+            stored,
+            minimum_version,
+            code: CodeWindow::new(),
+            state: State::Sealed,
+            selected: 0,
+        }
+    }
+
+    pub fn unlocked(&self) -> bool {
+        matches!(self.state, State::Opened { unlocked: true, .. })
+    }
+
+    /// The index of the entry the device would type.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn press(&mut self, button: Button) -> Commands<N> {
+        let mut commands = Commands::new();
+        let mut send = |command| {
+            // no press sends more than three
+            let _ = commands.push(command);
+        };
+
+        let (entries, unlocked) = match &mut self.state {
+            State::Sealed => {
+                let code_key = self.code.enter(button, &self.stored.vault.kdf);
+                let Ok(key) = self.stored.vault.open(&code_key) else {
+                    return commands;
+                };
+                self.code.clear();
+
+                // the MAC covers every entry, so any that won't decrypt means tampering
+                let decrypted = self
+                    .stored
+                    .entries(&key)
+                    .and_then(|mut opened| opened.try_fold(heapless::Vec::new(), collect));
+                let Ok(entries) = decrypted else {
+                    self.state = State::Unusable;
+                    send(Command::Lcd(LcdMessage::NoVault(VaultProblem::Damaged)));
+                    return commands;
+                };
+
+                // the MAC covers the version too, so now it can be trusted
+                let version = self.stored.vault.version;
+                if self
+                    .stored
+                    .vault
+                    .check_version(self.minimum_version)
+                    .is_err()
+                {
+                    self.state = State::Unusable;
+                    send(Command::Lcd(LcdMessage::NoVault(VaultProblem::Rollback)));
+                    return commands;
+                }
+                if version > self.minimum_version {
+                    send(Command::KeepVersion(version));
+                }
+
+                send(Command::Lcd(LcdMessage::SetName(display_name(
+                    entries.first(),
+                ))));
+                send(Command::Lcd(LcdMessage::Unlock));
+                self.state = State::Opened {
+                    entries,
+                    unlocked: true,
+                };
+                return commands;
+            }
+            State::Opened { entries, unlocked } => (entries, unlocked),
+            State::Unusable => return commands,
+        };
+
+        if !*unlocked {
+            let code_key = self.code.enter(button, &self.stored.vault.kdf);
+            match self.stored.vault.verify(&code_key) {
+                Ok(()) => {
+                    *unlocked = true;
+                    self.code.clear();
+                    send(Command::Lcd(LcdMessage::Unlock));
+                }
+                Err(_) => send(Command::Lcd(LcdMessage::Wake)),
+            }
+            return commands;
+        }
+
+        // an entry that isn't made of fields, such as one sealed from bare bytes, has
+        // nothing to type
+        let entry = entries
+            .get(self.selected)
+            .and_then(|entry| Entry::parse(entry).ok());
+        match button {
+            Button::A => {
+                *unlocked = false;
+                send(Command::Lcd(LcdMessage::Lock));
+            }
+            Button::B => {
+                if !entries.is_empty() {
+                    self.selected = (self.selected + 1) % entries.len();
+                }
+                send(Command::Lcd(LcdMessage::SetName(display_name(
+                    entries.get(self.selected),
+                ))));
+            }
+            Button::X => {
+                if let Some(entry) = entry {
+                    if let (Ok(username), Ok(password)) = (
+                        Plaintext::from_slice(entry.username()),
+                        Plaintext::from_slice(entry.password()),
+                    ) {
+                        send(Command::Usb(UsbMessage::Credentials { username, password }));
+                    }
+                }
+            }
+            Button::Y => {
+                if let Some(Ok(password)) =
+                    entry.map(|entry| Plaintext::from_slice(entry.password()))
+                {
+                    send(Command::Usb(UsbMessage::Password { password }));
+                }
+            }
+        }
+        commands
+    }
+}
+
+/// Adds a decrypted entry to those before it. There's room for all of them, as the
+/// vault holds no more than `MAX`.
+fn collect<const N: usize, const MAX: usize>(
+    mut entries: heapless::Vec<Plaintext<N>, MAX>,
+    entry: Result<Plaintext<N>, EndecError>,
+) -> Result<heapless::Vec<Plaintext<N>, MAX>, EndecError> {
+    entries
+        .push(entry?)
+        .map_err(|_| EndecError::IncorrectArraySize)?;
+    Ok(entries)
+}
+
+/// The LCD has room for exactly four characters, so pad or truncate to fit. Without an
+/// entry, or a name that can be read from it, the LCD shows `????`.
+fn display_name<const N: usize>(plaintext: Option<&Plaintext<N>>) -> [u8; 4] {
+    let Some(name) = plaintext.and_then(|plaintext| Entry::parse(plaintext).ok()) else {
+        return *b"????";
+    };
+    let name = name.name();
+
+    let mut display = *b"    ";
+    let len = name.len().min(display.len());
+    display[..len].copy_from_slice(&name[..len]);
+    display
+}
+
+/// The last `L` buttons pressed, which are tried as an unlock code after every press.
+struct CodeWindow<const L: usize> {
+    window: [u8; L],
+    /// Where the next press goes, which is also where the oldest one is.
+    next: usize,
+}
+
+impl<const L: usize> CodeWindow<L> {
+    fn new() -> Self {
+        CodeWindow {
+            window: [0; L],
+            next: 0,
+        }
+    }
+
+    /// Adds `button` to the window, returning the key derived from the code it now holds.
+    fn enter(&mut self, button: Button, kdf: &Kdf) -> Key {
+        self.window[self.next] = button.code();
+        self.next = (self.next + 1) % L;
+
+        let mut code = self.window;
+        code.rotate_left(self.next);
+        kdf.derive(&code)
+    }
+
+    /// Forgets the code, so that it has to be entered in full again.
+    fn clear(&mut self) {
+        self.window = [0; L];
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{App, Button, Command, Commands, LcdMessage, UsbMessage, VaultProblem};
+use etpwtc::{heapless, image_contents, vault, EndecError, LoadError, StoredVault};
+use Button::*;
+
+static IMAGE: &[u8] = vault!(
+    codes = [b"xyxyab"],
+    entries = [
+        { name: b"one", username: b"user1", password: b"pass1" },
+        { name: b"second", username: b"user2", password: b"pass2" },
+        { name: b"3", password: b"pass3" },
+    ],
+    rounds = 1,
+    capacity = 64,
+    image = 1024,
+);
+
+/// The vault in `image`, as the firmware loads it with room for four entries.
+fn stored(image: &[u8]) -> StoredVault<64, 4> {
+    StoredVault::decode(image_contents(image).unwrap()).unwrap()
+}
+
+fn app() -> App<64, 4, 6> {
+    App::new(stored(IMAGE), 1)
+}
+
+fn press_all(app: &mut App<64, 4, 6>, buttons: &[Button]) -> Commands<64> {
+    let mut last = Commands::new();
+    for button in buttons {
+        last = app.press(*button);
+    }
+    last
+}
+
+fn lcd(commands: &Commands<64>) -> heapless::Vec<&LcdMessage, 3> {
+    commands
+        .iter()
+        .map(|command| match command {
+            Command::Lcd(message) => message,
+            _ => panic!("expected only LCD commands: {commands:?}"),
+        })
+        .collect()
+}
+
+fn unlocked() -> App<64, 4, 6> {
+    let mut app = app();
+    press_all(&mut app, &[X, Y, X, Y, A, B]);
+    assert!(app.unlocked());
+    app
+}
+
+#[test]
+fn only_the_code_unlocks() {
+    let mut app = app();
+    for button in [A, B, A, B, X, Y, X, Y, X, Y, A] {
+        assert!(app.press(button).is_empty());
+        assert!(!app.unlocked());
+    }
+
+    // the last six presses are the code, whatever came before
+    assert_eq!(
+        lcd(&app.press(B)),
+        [&LcdMessage::SetName(*b"one "), &LcdMessage::Unlock]
+    );
+    assert!(app.unlocked());
+    assert_eq!(0, app.selected());
+}
+
+#[test]
+fn locking_needs_the_code_again() {
+    let mut app = unlocked();
+    assert_eq!(lcd(&app.press(A)), [&LcdMessage::Lock]);
+    assert!(!app.unlocked());
+
+    // the code is entered afresh, so its last presses from before don't count
+    for button in [X, Y, X, Y, A] {
+        assert_eq!(lcd(&app.press(button)), [&LcdMessage::Wake]);
+    }
+    assert_eq!(lcd(&app.press(B)), [&LcdMessage::Unlock]);
+    assert!(app.unlocked());
+}
+
+#[test]
+fn locked_presses_dont_type() {
+    let mut app = unlocked();
+    app.press(A);
+    for button in [X, Y, B, X, Y] {
+        assert!(matches!(
+            app.press(button).as_slice(),
+            [Command::Lcd(LcdMessage::Wake)]
+        ));
+    }
+}
+
+#[test]
+fn cycling_wraps_past_the_last_entry() {
+    let mut app = unlocked();
+    let names = [*b"seco", *b"3   ", *b"one ", *b"seco"];
+    for (i, name) in names.into_iter().enumerate() {
+        assert_eq!(lcd(&app.press(B)), [&LcdMessage::SetName(name)]);
+        assert_eq!((i + 1) % 3, app.selected());
+    }
+}
+
+#[test]
+fn selection_survives_locking() {
+    let mut app = unlocked();
+    app.press(B);
+    press_all(&mut app, &[A, X, Y, X, Y, A, B]);
+    assert!(app.unlocked());
+    assert_eq!(1, app.selected());
+}
+
+#[test]
+fn selected_entry_is_typed() {
+    let mut app = unlocked();
+    match app.press(X).as_slice() {
+        [Command::Usb(UsbMessage::Credentials { username, password })] => {
+            assert_eq!(b"user1", username.as_slice());
+            assert_eq!(b"pass1", password.as_slice());
+        }
+        other => panic!("{other:?}"),
+    }
+
+    press_all(&mut app, &[B, B]);
+    match app.press(Y).as_slice() {
+        [Command::Usb(UsbMessage::Password { password })] => {
+            assert_eq!(b"pass3", password.as_slice());
+        }
+        other => panic!("{other:?}"),
+    }
+}
+
+#[test]
+fn damaged_vault_is_no_vault() {
+    let mut stored = stored(IMAGE);
+    stored.vault.mac[0] ^= 1;
+    let mut app = App::new(stored, 1);
+    assert_eq!(
+        lcd(&press_all(&mut app, &[X, Y, X, Y, A, B])),
+        [&LcdMessage::NoVault(VaultProblem::Damaged)]
+    );
+
+    // it stays that way, whatever's pressed
+    for button in [A, B, X, Y, X, Y, X, Y, A, B] {
+        assert!(app.press(button).is_empty());
+        assert!(!app.unlocked());
+    }
+}
+
+#[test]
+fn bare_entries_have_no_name_and_type_nothing() {
+    static BARE: &[u8] = vault!(
+        codes = [b"xyxyab"],
+        entries = [b"not fields", { name: b"one", password: b"pass1" }],
+        rounds = 1,
+        capacity = 64,
+        image = 1024,
+    );
+    let mut app = App::new(stored(BARE), 1);
+    assert_eq!(
+        lcd(&press_all(&mut app, &[X, Y, X, Y, A, B])),
+        [&LcdMessage::SetName(*b"????"), &LcdMessage::Unlock]
+    );
+    assert!(app.press(X).is_empty());
+    assert!(app.press(Y).is_empty());
+    assert_eq!(lcd(&app.press(B)), [&LcdMessage::SetName(*b"one ")]);
+}
+
+#[test]
+fn empty_vault_unlocks_with_nothing_to_type() {
+    static EMPTY: &[u8] = vault!(codes = [b"xyxyab"], rounds = 1, image = 1024);
+    let mut app = App::new(stored(EMPTY), 1);
+    press_all(&mut app, &[X, Y, X, Y, A, B]);
+    assert!(app.unlocked());
+    assert_eq!(lcd(&app.press(B)), [&LcdMessage::SetName(*b"????")]);
+    assert!(app.press(X).is_empty());
+    assert!(app.press(Y).is_empty());
+    assert_eq!(0, app.selected());
+}
+
+#[test]
+fn load_errors_say_what_went_wrong() {
+    let problem = |error: LoadError<()>| VaultProblem::from(&error);
+    assert_eq!(VaultProblem::Empty, problem(LoadError::Empty));
+    assert_eq!(VaultProblem::Flash, problem(LoadError::Storage(())));
+    assert_eq!(
+        VaultProblem::TooManyEntries,
+        problem(LoadError::Invalid(EndecError::TooManyEntries))
+    );
+    assert_eq!(
+        VaultProblem::BadImage,
+        problem(LoadError::Invalid(EndecError::BadMagic))
+    );
+}
+
+#[test]
+fn newer_vaults_raise_the_minimum_version() {
+    let mut app = App::new(stored(IMAGE), 0);
+    let commands = press_all(&mut app, &[X, Y, X, Y, A, B]);
+    assert!(
+        matches!(
+            commands.as_slice(),
+            [
+                Command::KeepVersion(1),
+                Command::Lcd(LcdMessage::SetName(_)),
+                Command::Lcd(LcdMessage::Unlock)
+            ]
+        ),
+        "{commands:?}"
+    );
+
+    // only the first time it's opened
+    app.press(A);
+    assert_eq!(
+        lcd(&press_all(&mut app, &[X, Y, X, Y, A, B])),
+        [&LcdMessage::Unlock]
+    );
+}
+
+#[test]
+fn older_vaults_are_rolled_back() {
+    let mut app = App::new(stored(IMAGE), 2);
+    assert_eq!(
+        lcd(&press_all(&mut app, &[X, Y, X, Y, A, B])),
+        [&LcdMessage::NoVault(VaultProblem::Rollback)]
+    );
+    assert!(!app.unlocked());
+    assert!(press_all(&mut app, &[X, Y, X, Y, A, B]).is_empty());
+}
@@ -0,0 +1,82 @@
+//! Reading entries from an unencrypted Bitwarden JSON export. Only logins are read,
+//! as `name`, `username`, `password` and `url` (the first of the login's URIs).
+//!
+//! `folder` picks a folder by name, and `tag` a collection, for exports from an
+//! organisation; Bitwarden has no tags as such.
+
+use crate::{
+    import::{Fields, Filter, Problem},
+    json::{Spanned, Value},
+};
+
+pub(crate) fn entries(root: &Spanned, filter: &Filter) -> Result<Vec<Fields>, Problem> {
+    if let Some(
+        encrypted @ Spanned {
+            value: Value::Bool(true),
+            ..
+        },
+    ) = root.get("encrypted")
+    {
+        return Err(encrypted
+            .problem("encrypted exports can't be read, export as unencrypted JSON instead"));
+    }
+
+    let folder = find(root, "folders", filter.folder.as_deref())?;
+    let collection = find(root, "collections", filter.tag.as_deref())?;
+
+    let mut entries = Vec::new();
+    for item in root.array("items")? {
+        let Some(login) = item.get("login") else {
+            continue;
+        };
+        if folder.is_some() && item.string("folderId")? != folder {
+            continue;
+        }
+        if let Some(collection) = collection {
+            let mut ids = item.array("collectionIds")?.iter();
+            if !ids.any(|id| matches!(&id.value, Value::String(id) if id == collection)) {
+                continue;
+            }
+        }
+
+        let mut fields = Fields::new();
+        let uri = match login.array("uris")?.first() {
+            Some(uri) => uri.string("uri")?,
+            None => None,
+        };
+        let values = [
+            ("name", item.string("name")?),
+            ("username", login.string("username")?),
+            ("password", login.string("password")?),
+            ("url", uri),
+        ];
+        for (label, value) in values {
+            if let Some(value) = value {
+                fields.push((label.into(), value.into()));
+            }
+        }
+        entries.push(fields);
+    }
+    Ok(entries)
+}
+
+/// Looks up the ID of a folder or collection by name.
+fn find<'a>(root: &'a Spanned, list: &str, name: Option<&str>) -> Result<Option<&'a str>, Problem> {
+    let Some(name) = name else {
+        return Ok(None);
+    };
+    for element in root.array(list)? {
+        if element.string("name")? == Some(name) {
+            return element
+                .string("id")?
+                .map(Some)
+                .ok_or_else(|| element.problem("expected an `id`"));
+        }
+    }
+
+    let kind = list.trim_end_matches('s');
+    Err(Problem {
+        at: None,
+        message: format!("there's no {kind} `{name}`"),
+    })
+}
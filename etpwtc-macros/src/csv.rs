@@ -0,0 +1,182 @@
+//! Reading entries from CSV exports, such as those of browsers and Bitwarden. Columns
+//! are recognised by their headers, in any order and case:
+//!
+//! | field      | headers                                 |
+//! |------------|-----------------------------------------|
+//! | `name`     | `name`, `title`                         |
+//! | `username` | `username`, `login_username`, `login`   |
+//! | `password` | `password`, `login_password`            |
+//! | `url`      | `url`, `uri`, `login_uri`               |
+//!
+//! `folder` matches a `folder`, `group` or `grouping` column, and `tag` one of the
+//! comma or semicolon separated values in a `tags` column. Only a `password` column
+//! is required; without a name, entries are named after their URL's host. Rows whose
+//! `type` column isn't `login` are skipped.
+
+use crate::import::{Fields, Filter, Problem};
+
+const COLUMNS: [(&str, &[&str]); 4] = [
+    ("name", &["name", "title"]),
+    ("username", &["username", "login_username", "login"]),
+    ("password", &["password", "login_password"]),
+    ("url", &["url", "uri", "login_uri"]),
+];
+const FOLDER: &[&str] = &["folder", "group", "grouping"];
+const TAGS: &[&str] = &["tags", "tag"];
+
+pub(crate) fn entries(text: &str, filter: &Filter) -> Result<Vec<Fields>, Problem> {
+    let mut records = records(text)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Err(Problem {
+            at: None,
+            message: "the file is empty".into(),
+        });
+    };
+
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|h| names.iter().any(|name| h.trim().eq_ignore_ascii_case(name)))
+    };
+    let columns: Vec<_> = COLUMNS
+        .iter()
+        .map(|(label, names)| (*label, column(names)))
+        .collect();
+    if column(COLUMNS[2].1).is_none() {
+        return Err(Problem {
+            at: Some(0),
+            message: "there's no `password` column".into(),
+        });
+    }
+    let kind = column(&["type"]);
+    let folder = match &filter.folder {
+        Some(_) => Some(column(FOLDER).ok_or_else(|| Problem {
+            at: Some(0),
+            message: "there's no `folder` column to filter by".into(),
+        })?),
+        None => None,
+    };
+    let tags = match &filter.tag {
+        Some(_) => Some(column(TAGS).ok_or_else(|| Problem {
+            at: Some(0),
+            message: "there's no `tags` column to filter by".into(),
+        })?),
+        None => None,
+    };
+
+    let mut entries = Vec::new();
+    for (at, record) in records {
+        if record.len() != header.len() {
+            return Err(Problem {
+                at: Some(at),
+                message: format!(
+                    "expected {} fields, as in the header, but found {}",
+                    header.len(),
+                    record.len()
+                ),
+            });
+        }
+        if kind.is_some_and(|kind| !record[kind].eq_ignore_ascii_case("login")) {
+            continue;
+        }
+        if let (Some(column), Some(folder)) = (folder, &filter.folder) {
+            if record[column] != *folder {
+                continue;
+            }
+        }
+        if let (Some(column), Some(tag)) = (tags, &filter.tag) {
+            if !record[column].split([',', ';']).any(|t| t.trim() == tag) {
+                continue;
+            }
+        }
+
+        let mut fields = Fields::new();
+        for (label, column) in &columns {
+            if let Some(column) = column {
+                fields.push((label.to_string(), record[*column].clone()));
+            }
+        }
+        let named = fields
+            .iter()
+            .any(|(label, value)| label == "name" && !value.is_empty());
+        if !named {
+            fields.retain(|(label, _)| label != "name");
+            if let Some(host) = fields
+                .iter()
+                .find(|(label, _)| label == "url")
+                .and_then(|(_, url)| host(url))
+            {
+                fields.insert(0, ("name".into(), host.into()));
+            }
+        }
+        entries.push(fields);
+    }
+    Ok(entries)
+}
+
+fn host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#', ':']).next()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    (!host.is_empty()).then_some(host)
+}
+
+/// Splits `text` into records of fields, as in RFC 4180, each with the position where
+/// it starts. Blank lines are skipped.
+fn records(text: &str) -> Result<Vec<(usize, Vec<String>)>, Problem> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let offset = |rest: &str| text.len() - rest.len();
+
+    let mut records = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let start = offset(rest);
+        let mut record = Vec::new();
+        loop {
+            let mut field = String::new();
+            if let Some(quoted) = rest.strip_prefix('"') {
+                rest = quoted;
+                loop {
+                    let Some(end) = rest.find('"') else {
+                        return Err(Problem {
+                            at: Some(offset(rest)),
+                            message: "unterminated quoted field".into(),
+                        });
+                    };
+                    field.push_str(&rest[..end]);
+                    rest = &rest[end + 1..];
+                    match rest.strip_prefix('"') {
+                        Some(after) => {
+                            field.push('"');
+                            rest = after;
+                        }
+                        None => break,
+                    }
+                }
+                if !(rest.is_empty() || rest.starts_with([',', '\r', '\n'])) {
+                    return Err(Problem {
+                        at: Some(offset(rest)),
+                        message: "expected `,` or the end of the line after a quoted field".into(),
+                    });
+                }
+            } else {
+                let end = rest.find([',', '\r', '\n']).unwrap_or(rest.len());
+                field.push_str(&rest[..end]);
+                rest = &rest[end..];
+            }
+            record.push(field);
+
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None => break,
+            }
+        }
+
+        rest = rest.strip_prefix("\r").unwrap_or(rest);
+        rest = rest.strip_prefix("\n").unwrap_or(rest);
+        if record.len() > 1 || !record[0].is_empty() {
+            records.push((start, record));
+        }
+    }
+    Ok(records)
+}
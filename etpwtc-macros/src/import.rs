@@ -0,0 +1,388 @@
+//! Reading entries from files at compile time. Nothing read here reaches the expanded
+//! code except as ciphertext.
+//!
+//! Entry files hold a list of entries, each a table of string fields. `name`,
+//! `username` and `password` are stored as such; anything else becomes an extra field.
+//!
+//! ```toml
+//! [[entries]]
+//! name = "ABCD"
+//! password = "sw0rd*f1sh"
+//! url = "https://example.com/login"
+//! ```
+//!
+//! ```json
+//! { "entries": [{ "name": "ABCD", "password": "sw0rd*f1sh" }] }
+//! ```
+//!
+//! Password manager exports can be read too: KeePass databases given `group` and
+//! `kdbx_password` (see `kdbx`), and Bitwarden JSON and CSV exports, which can be
+//! narrowed down with `folder` and `tag` (see `bitwarden` and `csv`).
+
+use crate::{bitwarden, csv, json, kdbx, untypable, warning, Options, Plaintext};
+use etpwtc_runtime::{Field, TAG_LEN};
+use proc_macro2::TokenStream;
+use std::{ops::Range, path::PathBuf};
+use syn::{Error, LitByteStr, LitStr, Result};
+use toml_edit::{ImDocument, Item, Value};
+
+pub(crate) type Fields = Vec<(String, String)>;
+
+/// The LCD has room for this many characters of an entry's name.
+const DISPLAY_WIDTH: usize = 4;
+
+/// A problem in the file, at a byte offset into it where one is known.
+pub(crate) struct Problem {
+    pub(crate) at: Option<usize>,
+    pub(crate) message: String,
+}
+
+impl Problem {
+    fn new(span: Option<Range<usize>>, message: impl Into<String>) -> Self {
+        Problem {
+            at: span.map(|span| span.start),
+            message: message.into(),
+        }
+    }
+}
+
+/// Which entries of an export to read, by the folder they're in or a tag they have.
+pub(crate) struct Filter {
+    pub(crate) folder: Option<String>,
+    pub(crate) tag: Option<String>,
+}
+
+/// How to fit imported names onto the LCD.
+#[derive(Clone, Copy)]
+pub(crate) enum ShortNames {
+    /// Keep the first few characters.
+    Truncate,
+    /// Make an upper-case abbreviation, from the initials of several words or the
+    /// start of one, ignoring `www.` and the top-level domain of names which look
+    /// like host names.
+    Derive,
+}
+
+/// Reads a `.toml`, `.json`, `.csv` or `.kdbx` file, relative to the invoking crate's
+/// manifest, leaving out entries too long for the capacity. Also returns items which
+/// warn about those.
+///
+/// As with key files, the invoking crate's build script should print
+/// `cargo:rerun-if-changed=PATH` for it to be rebuilt when the file changes; see
+/// `KeySource`.
+pub(crate) fn load(path: &LitStr, options: &Options) -> Result<(Vec<Plaintext>, TokenStream)> {
+    let resolved = resolve(&path.value());
+    let bytes = std::fs::read(&resolved).map_err(|e| {
+        Error::new(
+            path.span(),
+            format!("couldn't read `{}`: {e}", resolved.display()),
+        )
+    })?;
+    let mut warnings = TokenStream::new();
+
+    let extension = resolved.extension().and_then(|e| e.to_str());
+    let filter = Filter {
+        folder: options.folder.as_ref().map(LitStr::value),
+        tag: options.tag.as_ref().map(LitStr::value),
+    };
+    let unfiltered = || {
+        let setting = options.folder.as_ref().or(options.tag.as_ref());
+        match setting {
+            Some(setting) => Err(Error::new(
+                setting.span(),
+                "`folder` and `tag` only apply to Bitwarden and CSV exports",
+            )),
+            None => Ok(()),
+        }
+    };
+
+    let mut entries = if extension == Some("kdbx") {
+        unfiltered()?;
+        let (Some(group), Some(password)) = (&options.group, &options.kdbx_password) else {
+            return Err(Error::new(
+                path.span(),
+                "a KeePass database needs `group = \"...\"` and `kdbx_password = ...`",
+            ));
+        };
+        let password = password.resolve()?;
+        kdbx::entries(&bytes, &password, &group.value())
+            .map_err(|e| Error::new(path.span(), format!("{}: {e}", path.value())))?
+    } else {
+        if let Some(group) = &options.group {
+            return Err(Error::new(
+                group.span(),
+                "`group` only applies to KeePass databases",
+            ));
+        }
+        let text = String::from_utf8(bytes)
+            .map_err(|_| Error::new(path.span(), format!("{}: not UTF-8", path.value())))?;
+
+        match extension {
+            Some("toml") => {
+                unfiltered()?;
+                toml_entries(&text)
+            }
+            // a Bitwarden export has `items` where an entry file has `entries`
+            Some("json") => match json::parse(&text) {
+                Ok(root) if root.get("items").is_some() => bitwarden::entries(&root, &filter),
+                Ok(root) => {
+                    unfiltered()?;
+                    json_entries(&root)
+                }
+                Err(problem) => Err(problem),
+            },
+            Some("csv") => csv::entries(&text, &filter),
+            _ => {
+                return Err(Error::new(
+                    path.span(),
+                    "expected a `.toml`, `.json`, `.csv` or `.kdbx` file",
+                ))
+            }
+        }
+        .map_err(|problem| located(path, &text, problem))?
+    };
+
+    if let Some(how) = options.short_names {
+        shorten(&mut entries, how);
+    }
+
+    let mut kept = Vec::new();
+    for (i, fields) in entries.iter().enumerate() {
+        let plaintext = entry(path, i, fields)?;
+        if plaintext.value()?.len() + TAG_LEN < options.capacity {
+            kept.push(plaintext);
+            continue;
+        }
+
+        let name = match fields.iter().find(|(label, _)| label == "name") {
+            Some((_, name)) => format!("`{name}`"),
+            None => format!("entry {}", i + 1),
+        };
+        let message = format!(
+            "{}: {name} is too long to seal at a capacity of {} bytes, so it's left out",
+            path.value(),
+            options.capacity
+        );
+        warnings.extend(warning(path.span(), &message));
+    }
+    Ok((kept, warnings))
+}
+
+/// Resolves `path` against the invoking crate's manifest directory, or against
+/// `ETPWTC_BASE_DIR` if its build script sets one with `cargo:rustc-env`, as `hwsim`'s
+/// does for the firmware's `secrets.rs` so that it reads the same files either way.
+pub(crate) fn resolve(path: &str) -> PathBuf {
+    let base = std::env::var_os("ETPWTC_BASE_DIR")
+        .or_else(|| std::env::var_os("CARGO_MANIFEST_DIR"))
+        .unwrap_or_default();
+    PathBuf::from(base).join(path)
+}
+
+fn entry(path: &LitStr, index: usize, fields: &Fields) -> Result<Plaintext> {
+    let error = |message: String| {
+        Error::new(
+            path.span(),
+            format!("{}: entry {}: {message}", path.value(), index + 1),
+        )
+    };
+
+    let mut encoded = Vec::new();
+    for field in Field::ALL {
+        if let Some((_, value)) = fields.iter().find(|(label, _)| label == field.label()) {
+            if field != Field::Name {
+                if let Some(problem) = untypable(value.as_bytes()) {
+                    return Err(error(format!("`{}`: {problem}", field.label())));
+                }
+            }
+            encoded.push((field, value.as_bytes().to_vec()));
+        }
+    }
+    for (label, value) in fields {
+        if Field::from_label(label).is_some() {
+            continue;
+        }
+        if label.contains('\0') {
+            return Err(error("field labels can't contain NUL".into()));
+        }
+
+        let mut extra = label.as_bytes().to_vec();
+        extra.push(0);
+        extra.extend_from_slice(value.as_bytes());
+        encoded.push((Field::Extra, extra));
+    }
+
+    if let Some((field, _)) = encoded.iter().find(|(_, value)| value.len() > 255) {
+        return Err(error(format!(
+            "`{}` is longer than the 255 bytes a field can hold",
+            field.label()
+        )));
+    }
+
+    let fields = encoded
+        .into_iter()
+        .map(|(field, value)| (field, LitByteStr::new(&value, path.span())))
+        .collect();
+    Ok(Plaintext::Entry(fields))
+}
+
+fn toml_entries(text: &str) -> std::result::Result<Vec<Fields>, Problem> {
+    let document =
+        ImDocument::parse(text).map_err(|e| Problem::new(e.span(), e.message().trim_end()))?;
+
+    let mut entries = Vec::new();
+    for (key, item) in document.iter() {
+        if key != "entries" {
+            let span = document.key(key).and_then(|key| key.span());
+            return Err(Problem::new(span, format!("unknown key `{key}`")));
+        }
+
+        match item {
+            Item::ArrayOfTables(tables) => {
+                for table in tables {
+                    let fields = table
+                        .iter()
+                        .map(|(label, item)| (label, item.as_str(), item.span()));
+                    entries.push(toml_fields(table.span(), fields)?);
+                }
+            }
+            Item::Value(Value::Array(array)) => {
+                for value in array {
+                    let Value::InlineTable(table) = value else {
+                        return Err(Problem::new(value.span(), "expected a table"));
+                    };
+                    let fields = table
+                        .iter()
+                        .map(|(label, value)| (label, value.as_str(), value.span()));
+                    entries.push(toml_fields(table.span(), fields)?);
+                }
+            }
+            _ => return Err(Problem::new(item.span(), "expected an array of tables")),
+        }
+    }
+    Ok(entries)
+}
+
+/// Collects a table's fields, given each one's label, string value and span.
+fn toml_fields<'a>(
+    span: Option<Range<usize>>,
+    items: impl Iterator<Item = (&'a str, Option<&'a str>, Option<Range<usize>>)>,
+) -> std::result::Result<Fields, Problem> {
+    let mut fields = Fields::new();
+    for (label, value, value_span) in items {
+        let Some(value) = value else {
+            let span = value_span.or(span.clone());
+            return Err(Problem::new(span, format!("`{label}` should be a string")));
+        };
+        fields.push((label.into(), value.into()));
+    }
+    Ok(fields)
+}
+
+fn json_entries(root: &json::Spanned) -> std::result::Result<Vec<Fields>, Problem> {
+    let json::Value::Object(members) = &root.value else {
+        return Err(root.problem("expected an object"));
+    };
+    let mut entries = Vec::new();
+    for (at, key, value) in members {
+        if key != "entries" {
+            return Err(Problem::new(Some(*at..*at), format!("unknown key `{key}`")));
+        }
+        let json::Value::Array(elements) = &value.value else {
+            return Err(value.problem("expected an array of objects"));
+        };
+        for element in elements {
+            let json::Value::Object(members) = &element.value else {
+                return Err(element.problem("expected an object"));
+            };
+            let mut fields = Fields::new();
+            for (_, label, value) in members {
+                let json::Value::String(value) = &value.value else {
+                    return Err(value.problem(format!("`{label}` should be a string")));
+                };
+                fields.push((label.clone(), value.clone()));
+            }
+            entries.push(fields);
+        }
+    }
+    Ok(entries)
+}
+
+/// Replaces names too long for the LCD with short ones, keeping the original as an
+/// extra `title` field. Short names are numbered where they would otherwise clash.
+fn shorten(entries: &mut [Fields], how: ShortNames) {
+    let mut taken: Vec<String> = Vec::new();
+    for fields in entries {
+        let Some(index) = fields.iter().position(|(label, _)| label == "name") else {
+            continue;
+        };
+        let name = &fields[index].1;
+        if name.chars().count() <= DISPLAY_WIDTH && !taken.contains(name) {
+            taken.push(name.clone());
+            continue;
+        }
+
+        let base = match how {
+            ShortNames::Truncate => name.chars().take(DISPLAY_WIDTH).collect(),
+            ShortNames::Derive => abbreviate(name),
+        };
+        let mut short = base.clone();
+        for n in 2.. {
+            if !taken.contains(&short) {
+                break;
+            }
+            let suffix = n.to_string();
+            let kept = DISPLAY_WIDTH.saturating_sub(suffix.len());
+            short = base.chars().take(kept).collect::<String>() + &suffix;
+        }
+
+        taken.push(short.clone());
+        let original = std::mem::replace(&mut fields[index].1, short);
+        if fields[index].1 != original {
+            fields.push(("title".into(), original));
+        }
+    }
+}
+
+fn abbreviate(name: &str) -> String {
+    let mut name = name.trim();
+    if !name.contains(char::is_whitespace) && name.contains('.') {
+        // `www.example.com` becomes `example`
+        let host = name.split_once("://").map_or(name, |(_, rest)| rest);
+        let host = host.split('/').next().unwrap_or(host);
+        let host = host.strip_prefix("www.").unwrap_or(host);
+        name = host.rsplit_once('.').map_or(host, |(rest, _)| rest);
+    }
+
+    let words: Vec<_> = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let short: String = match words.as_slice() {
+        [] => name.chars().take(DISPLAY_WIDTH).collect(),
+        [word] => word.chars().take(DISPLAY_WIDTH).collect(),
+        words => words
+            .iter()
+            .filter_map(|word| word.chars().next())
+            .take(DISPLAY_WIDTH)
+            .collect(),
+    };
+    short.to_uppercase()
+}
+
+fn located(path: &LitStr, text: &str, problem: Problem) -> Error {
+    let Some(at) = problem.at else {
+        return Error::new(
+            path.span(),
+            format!("{}: {}", path.value(), problem.message),
+        );
+    };
+
+    let before = &text[..at];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    Error::new(
+        path.span(),
+        format!("{}:{line}:{column}: {}", path.value(), problem.message),
+    )
+}
@@ -0,0 +1,262 @@
+//! Just enough JSON to read entry files and password manager exports, keeping the
+//! position of each value so that problems can be reported against the file.
+
+use crate::import::Problem;
+
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    /// Numbers are kept as written, since nothing read here needs their value.
+    Number,
+    String(String),
+    Array(Vec<Spanned>),
+    /// Members in order, each with the position of its key.
+    Object(Vec<(usize, String, Spanned)>),
+}
+
+pub(crate) struct Spanned {
+    pub(crate) at: usize,
+    pub(crate) value: Value,
+}
+
+impl Spanned {
+    pub(crate) fn get(&self, key: &str) -> Option<&Spanned> {
+        match &self.value {
+            Value::Object(members) => members
+                .iter()
+                .find(|(_, name, _)| name == key)
+                .map(|(_, _, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The string at `key`, treating `null` and a missing key alike.
+    pub(crate) fn string(&self, key: &str) -> Result<Option<&str>, Problem> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Spanned {
+                value: Value::Null, ..
+            }) => Ok(None),
+            Some(Spanned {
+                value: Value::String(value),
+                ..
+            }) => Ok(Some(value)),
+            Some(other) => Err(other.problem(format!("`{key}` should be a string"))),
+        }
+    }
+
+    /// The elements of the array at `key`, treating `null` and a missing key as empty.
+    pub(crate) fn array(&self, key: &str) -> Result<&[Spanned], Problem> {
+        match self.get(key) {
+            None => Ok(&[]),
+            Some(Spanned {
+                value: Value::Null, ..
+            }) => Ok(&[]),
+            Some(Spanned {
+                value: Value::Array(elements),
+                ..
+            }) => Ok(elements),
+            Some(other) => Err(other.problem(format!("`{key}` should be an array"))),
+        }
+    }
+
+    pub(crate) fn problem(&self, message: impl Into<String>) -> Problem {
+        Problem {
+            at: Some(self.at),
+            message: message.into(),
+        }
+    }
+}
+
+pub(crate) fn parse(text: &str) -> Result<Spanned, Problem> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.problem("trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Nesting deeper than this is refused rather than risking the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self, depth: usize) -> Result<Spanned, Problem> {
+        if depth > MAX_DEPTH {
+            return Err(self.problem("nested too deeply"));
+        }
+
+        self.whitespace();
+        let at = self.pos;
+        let rest = &self.text[at..];
+        let value = match rest.as_bytes().first() {
+            Some(b'{') => {
+                let mut members: Vec<(usize, String, Spanned)> = Vec::new();
+                self.pos += 1;
+                if !self.eat(b'}') {
+                    loop {
+                        self.whitespace();
+                        let key_at = self.pos;
+                        let key = self.string()?;
+                        if members.iter().any(|(_, existing, _)| *existing == key) {
+                            return Err(self.problem_at(key_at, format!("duplicate key `{key}`")));
+                        }
+                        self.expect(b':', "expected `:`")?;
+                        members.push((key_at, key, self.value(depth + 1)?));
+                        if !self.eat(b',') {
+                            self.expect(b'}', "expected `,` or `}`")?;
+                            break;
+                        }
+                    }
+                }
+                Value::Object(members)
+            }
+            Some(b'[') => {
+                let mut elements = Vec::new();
+                self.pos += 1;
+                if !self.eat(b']') {
+                    loop {
+                        elements.push(self.value(depth + 1)?);
+                        if !self.eat(b',') {
+                            self.expect(b']', "expected `,` or `]`")?;
+                            break;
+                        }
+                    }
+                }
+                Value::Array(elements)
+            }
+            Some(b'"') => Value::String(self.string()?),
+            _ if rest.starts_with("null") => {
+                self.pos += 4;
+                Value::Null
+            }
+            _ if rest.starts_with("true") => {
+                self.pos += 4;
+                Value::Bool(true)
+            }
+            _ if rest.starts_with("false") => {
+                self.pos += 5;
+                Value::Bool(false)
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let len = rest
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(rest.len());
+                self.pos += len;
+                Value::Number
+            }
+            _ => return Err(self.problem("expected a value")),
+        };
+
+        Ok(Spanned { at, value })
+    }
+
+    fn string(&mut self) -> Result<String, Problem> {
+        self.expect(b'"', "expected a string")?;
+        let mut string = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let Some(c) = rest.chars().next() else {
+                return Err(self.problem("unterminated string"));
+            };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    string.push(self.escape()?);
+                }
+                c if c < ' ' => return Err(self.problem("control character in string")),
+                c => {
+                    self.pos += c.len_utf8();
+                    string.push(c);
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Problem> {
+        let at = self.pos;
+        let escaped = self.text.as_bytes().get(self.pos).copied();
+        self.pos += 1;
+        Ok(match escaped {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                let high = self.hex()?;
+                let code = if (0xd800..0xdc00).contains(&high) {
+                    if !self.text[self.pos..].starts_with("\\u") {
+                        return Err(self.problem_at(at, "unpaired surrogate"));
+                    }
+                    self.pos += 2;
+                    let low = self.hex()?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(self.problem_at(at, "unpaired surrogate"));
+                    }
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(|| self.problem_at(at, "unpaired surrogate"))?
+            }
+            _ => return Err(self.problem_at(at, "invalid escape")),
+        })
+    }
+
+    fn hex(&mut self) -> Result<u32, Problem> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.problem("expected four hex digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.whitespace();
+        let found = self.text.as_bytes().get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8, message: &str) -> Result<(), Problem> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.problem(message))
+        }
+    }
+
+    fn problem(&self, message: impl Into<String>) -> Problem {
+        self.problem_at(self.pos, message)
+    }
+
+    fn problem_at(&self, at: usize, message: impl Into<String>) -> Problem {
+        Problem {
+            at: Some(at),
+            message: message.into(),
+        }
+    }
+}
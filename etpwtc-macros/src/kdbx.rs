@@ -0,0 +1,521 @@
+//! Reading entries from a KeePass database in the KDBX 4 format, unlocked with a
+//! password. Key files, KDBX 3 and Twofish aren't supported.
+//!
+//! Only the entries directly in one group are read, which can be the root group, and
+//! of each only the title, user name, password and URL, which become `name`,
+//! `username`, `password` and `url`.
+//! Earlier versions of entries, kept in their history, are skipped.
+
+use crate::import::Fields;
+use aes::cipher::{
+    block_padding::Pkcs7, BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher,
+};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use quick_xml::events::{BytesStart, Event};
+use sha2::{Digest, Sha256, Sha512};
+use std::io::Read;
+
+const SIGNATURE: [u32; 2] = [0x9aa2_d903, 0xb54b_fb67];
+
+const AES256_CBC: [u8; 16] = uuid(0x31c1f2e6_bf71_4350_be58_05216afc5aff);
+const CHACHA20: [u8; 16] = uuid(0xd6038a2b_8b6f_4cb5_a524_339a31dbb59a);
+const AES_KDF: [u8; 16] = uuid(0xc9d9f39a_628a_4460_bf74_0d08c18a4fea);
+const AES_KDF_KDBX4: [u8; 16] = uuid(0x7c02bb82_79a7_4ac0_927d_114a00648238);
+const ARGON2D: [u8; 16] = uuid(0xef636ddf_8c29_444b_91f7_a9a403e30a0c);
+const ARGON2ID: [u8; 16] = uuid(0x9e298b19_56db_4773_b23d_fc3ec6f0a1e6);
+
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+const fn uuid(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+/// Decrypts `bytes` with `password` and returns the entries in `group`, a path of
+/// group names separated by `/`, starting below the root group. An empty path is the
+/// root group itself.
+pub(crate) fn entries(bytes: &[u8], password: &[u8], group: &str) -> Result<Vec<Fields>, String> {
+    let mut reader = Reader(bytes);
+    if [reader.u32()?, reader.u32()?] != SIGNATURE {
+        return Err("not a KeePass database".into());
+    }
+    let major = reader.u32()? >> 16;
+    if major != 4 {
+        return Err(format!(
+            "KDBX {major} isn't supported, only KDBX 4; save the database with a newer KeePass"
+        ));
+    }
+
+    let header = Header::read(&mut reader)?;
+    let header_bytes = &bytes[..bytes.len() - reader.0.len()];
+    if reader.take(32)? != Sha256::digest(header_bytes).as_slice() {
+        return Err("the header is corrupted".into());
+    }
+
+    let composite = Sha256::digest(Sha256::digest(password));
+    let transformed = header.kdf.transform(&composite)?;
+    let master_key = Sha256::new()
+        .chain_update(header.master_seed)
+        .chain_update(transformed)
+        .finalize();
+    let hmac_key = Sha512::new()
+        .chain_update(header.master_seed)
+        .chain_update(transformed)
+        .chain_update([1])
+        .finalize();
+
+    // a wrong password is only noticed here, since nothing else depends on the key
+    let header_mac = reader.take(32)?;
+    if block_mac(&hmac_key, u64::MAX, &[header_bytes])
+        .verify_slice(header_mac)
+        .is_err()
+    {
+        return Err("wrong password, or the header is corrupted".into());
+    }
+
+    // the payload is split into blocks, each with a MAC over its index, length and data
+    let mut payload = Vec::new();
+    for index in 0u64.. {
+        let mac = reader.take(32)?;
+        let len = reader.take(4)?;
+        let data = reader.take(u32::from_le_bytes(array(len)?) as usize)?;
+        if block_mac(&hmac_key, index, &[&index.to_le_bytes(), len, data])
+            .verify_slice(mac)
+            .is_err()
+        {
+            return Err(format!("block {index} is corrupted"));
+        }
+        if data.is_empty() {
+            break;
+        }
+        payload.extend_from_slice(data);
+    }
+
+    let iv_error = |_| "the encryption IV is the wrong length".to_string();
+    match header.cipher {
+        AES256_CBC => {
+            let decryptor = cbc::Decryptor::<aes::Aes256>::new_from_slices(&master_key, &header.iv)
+                .map_err(iv_error)?;
+            let len = decryptor
+                .decrypt_padded_mut::<Pkcs7>(&mut payload)
+                .map_err(|_| "the payload is corrupted")?
+                .len();
+            payload.truncate(len);
+        }
+        CHACHA20 => chacha20::ChaCha20::new_from_slices(&master_key, &header.iv)
+            .map_err(iv_error)?
+            .apply_keystream(&mut payload),
+        _ => return Err("only AES-256 and ChaCha20 databases are supported".into()),
+    }
+    if header.compressed {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(payload.as_slice())
+            .read_to_end(&mut decompressed)
+            .map_err(|e| format!("couldn't decompress the payload: {e}"))?;
+        payload = decompressed;
+    }
+
+    let mut reader = Reader(&payload);
+    let (mut stream_id, mut stream_key) = (None, None);
+    loop {
+        match reader.field()? {
+            (0, _) => break,
+            (1, id) => stream_id = Some(u32::from_le_bytes(array(id)?)),
+            (2, key) => stream_key = Some(key),
+            _ => {}
+        }
+    }
+    let stream = match (stream_id, stream_key) {
+        (Some(id), Some(key)) => InnerStream::new(id, key)?,
+        _ => return Err("the inner header has no stream cipher".into()),
+    };
+
+    let group: Vec<_> = match group {
+        "" => Vec::new(),
+        group => group.split('/').collect(),
+    };
+    Walk::new(stream, &group).run(reader.0)
+}
+
+fn block_mac(key: &[u8], index: u64, data: &[&[u8]]) -> Hmac<Sha256> {
+    let block_key = Sha512::new()
+        .chain_update(index.to_le_bytes())
+        .chain_update(key)
+        .finalize();
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&block_key).expect("HMAC accepts keys of any length");
+    for data in data {
+        mac.update(data);
+    }
+    mac
+}
+
+struct Header {
+    cipher: [u8; 16],
+    compressed: bool,
+    master_seed: [u8; 32],
+    iv: Vec<u8>,
+    kdf: Kdf,
+}
+
+impl Header {
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        let (mut cipher, mut compressed, mut master_seed, mut iv, mut kdf) =
+            (None, false, None, None, None);
+        loop {
+            match reader.field()? {
+                (0, _) => break,
+                (2, data) => cipher = Some(array(data)?),
+                (3, data) => compressed = u32::from_le_bytes(array(data)?) == 1,
+                (4, data) => master_seed = Some(array(data)?),
+                (7, data) => iv = Some(data.to_vec()),
+                (11, data) => kdf = Some(Kdf::read(data)?),
+                _ => {}
+            }
+        }
+
+        let missing = |field: &str| format!("the header has no {field}");
+        Ok(Header {
+            cipher: cipher.ok_or_else(|| missing("cipher"))?,
+            compressed,
+            master_seed: master_seed.ok_or_else(|| missing("master seed"))?,
+            iv: iv.ok_or_else(|| missing("encryption IV"))?,
+            kdf: kdf.ok_or_else(|| missing("KDF parameters"))?,
+        })
+    }
+}
+
+enum Kdf {
+    Aes {
+        rounds: u64,
+        seed: [u8; 32],
+    },
+    Argon2 {
+        algorithm: argon2::Algorithm,
+        params: argon2::Params,
+        version: argon2::Version,
+        salt: Vec<u8>,
+    },
+}
+
+impl Kdf {
+    /// Reads the KDF's parameters, stored as a list of typed `name = value` pairs.
+    fn read(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(bytes);
+        if reader.take(2)?[1] != 1 {
+            return Err("the KDF parameters are in an unknown format".into());
+        }
+        let mut values = Vec::new();
+        loop {
+            let kind = reader.take(1)?[0];
+            if kind == 0 {
+                break;
+            }
+            let name = reader.sized()?;
+            let value = reader.sized()?;
+            values.push((name, value));
+        }
+
+        let get = |name: &str| {
+            values
+                .iter()
+                .find(|(n, _)| *n == name.as_bytes())
+                .map(|(_, value)| *value)
+                .ok_or_else(|| format!("the KDF parameters have no `{name}`"))
+        };
+        // integers are stored as 32 or 64 bits depending on the parameter
+        let int = |name: &str| -> Result<u64, String> {
+            let value = get(name)?;
+            match value.len() {
+                4 => Ok(u32::from_le_bytes(array(value)?) as u64),
+                _ => Ok(u64::from_le_bytes(array(value)?)),
+            }
+        };
+        let int32 = |name: &str| {
+            int(name)?
+                .try_into()
+                .map_err(|_| format!("the KDF parameter `{name}` is out of range"))
+        };
+
+        let uuid: [u8; 16] = array(get("$UUID")?)?;
+        match uuid {
+            AES_KDF | AES_KDF_KDBX4 => Ok(Kdf::Aes {
+                rounds: int("R")?,
+                seed: array(get("S")?)?,
+            }),
+            ARGON2D | ARGON2ID => {
+                if get("K").is_ok() || get("A").is_ok() {
+                    return Err(
+                        "Argon2 with a secret key or associated data isn't supported".into(),
+                    );
+                }
+                let params =
+                    argon2::Params::new(int32("M")? / 1024, int32("I")?, int32("P")?, Some(32))
+                        .map_err(|e| format!("bad Argon2 parameters: {e}"))?;
+                Ok(Kdf::Argon2 {
+                    algorithm: match uuid {
+                        ARGON2D => argon2::Algorithm::Argon2d,
+                        _ => argon2::Algorithm::Argon2id,
+                    },
+                    params,
+                    version: argon2::Version::try_from(int32("V")?)
+                        .map_err(|e| format!("bad Argon2 version: {e}"))?,
+                    salt: get("S")?.to_vec(),
+                })
+            }
+            _ => Err("only AES-KDF and Argon2 databases are supported".into()),
+        }
+    }
+
+    fn transform(&self, composite: &[u8]) -> Result<[u8; 32], String> {
+        match self {
+            Kdf::Aes { rounds, seed } => {
+                let cipher = aes::Aes256::new(seed.into());
+                let mut key: [u8; 32] = array(composite)?;
+                for _ in 0..*rounds {
+                    for block in key.chunks_exact_mut(16) {
+                        cipher.encrypt_block(block.into());
+                    }
+                }
+                Ok(Sha256::digest(key).into())
+            }
+            Kdf::Argon2 {
+                algorithm,
+                params,
+                version,
+                salt,
+            } => {
+                let mut key = [0; 32];
+                argon2::Argon2::new(*algorithm, *version, params.clone())
+                    .hash_password_into(composite, salt, &mut key)
+                    .map_err(|e| format!("Argon2 failed: {e}"))?;
+                Ok(key)
+            }
+        }
+    }
+}
+
+/// The stream cipher which protected values, such as passwords, are XORed with. The
+/// stream runs through every protected value in the document in order, so each has
+/// to be decrypted whether it's wanted or not.
+enum InnerStream {
+    Salsa20(salsa20::Salsa20),
+    ChaCha20(chacha20::ChaCha20),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<Self, String> {
+        match id {
+            2 => {
+                let key = Sha256::digest(key);
+                Ok(InnerStream::Salsa20(salsa20::Salsa20::new(
+                    &key,
+                    &SALSA20_NONCE.into(),
+                )))
+            }
+            3 => {
+                let hash = Sha512::digest(key);
+                Ok(InnerStream::ChaCha20(
+                    chacha20::ChaCha20::new_from_slices(&hash[..32], &hash[32..44]).unwrap(),
+                ))
+            }
+            _ => Err("only Salsa20 and ChaCha20 protected values are supported".into()),
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+            InnerStream::ChaCha20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+/// A pass over the XML document, collecting the entries in the chosen group.
+struct Walk<'a> {
+    stream: InnerStream,
+    group: &'a [&'a str],
+    found: bool,
+    /// Names of the enclosing elements.
+    elements: Vec<String>,
+    /// Names of the enclosing groups, the first being the root group.
+    groups: Vec<String>,
+    /// The entry being collected, and how deeply it's nested.
+    entry: Option<(usize, Fields)>,
+    key: String,
+    value: String,
+    protected: bool,
+    entries: Vec<Fields>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(stream: InnerStream, group: &'a [&'a str]) -> Self {
+        Walk {
+            stream,
+            group,
+            found: false,
+            elements: Vec::new(),
+            groups: Vec::new(),
+            entry: None,
+            key: String::new(),
+            value: String::new(),
+            protected: false,
+            entries: Vec::new(),
+        }
+    }
+
+    fn run(mut self, xml: &[u8]) -> Result<Vec<Fields>, String> {
+        let mut reader = quick_xml::Reader::from_reader(xml);
+        loop {
+            let event = reader.read_event().map_err(|e| {
+                format!(
+                    "the XML is malformed at byte {}: {e}",
+                    reader.error_position()
+                )
+            })?;
+            match event {
+                Event::Start(start) => self.open(&start)?,
+                Event::Empty(start) => {
+                    self.open(&start)?;
+                    self.close()?;
+                }
+                Event::Text(text) => {
+                    let text = text.unescape().map_err(|e| e.to_string())?;
+                    self.text(&text)?;
+                }
+                Event::CData(text) => self.text(&String::from_utf8_lossy(&text))?,
+                Event::End(_) => self.close()?,
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        // the root group is always there, whatever it's called
+        if !self.found && !self.group.is_empty() {
+            return Err(format!("there's no group `{}`", self.group.join("/")));
+        }
+        Ok(self.entries)
+    }
+
+    fn parent(&self) -> &str {
+        self.elements.last().map_or("", String::as_str)
+    }
+
+    fn open(&mut self, start: &BytesStart) -> Result<(), String> {
+        let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+        match name.as_str() {
+            "Group" => self.groups.push(String::new()),
+            // entries in history are nested in another entry rather than a group
+            "Entry" if self.parent() == "Group" && self.groups[1..] == *self.group => {
+                self.entry = Some((self.elements.len(), Fields::new()));
+            }
+            "String" => {
+                self.key.clear();
+                self.value.clear();
+            }
+            "Value" => {
+                let protected = start
+                    .try_get_attribute("Protected")
+                    .map_err(|e| e.to_string())?;
+                self.protected = protected.is_some_and(|p| p.value.as_ref() == b"True");
+            }
+            _ => {}
+        }
+        self.elements.push(name);
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), String> {
+        let enclosing = match self.elements.as_slice() {
+            [.., outer, inner] => (outer.as_str(), inner.as_str()),
+            _ => return Ok(()),
+        };
+        match enclosing {
+            ("Group", "Name") => {
+                self.groups.last_mut().unwrap().push_str(text);
+                if self.groups[1..] == *self.group {
+                    self.found = true;
+                }
+            }
+            ("String", "Key") => self.key.push_str(text),
+            ("String", "Value") if self.protected => {
+                let mut value = base64::engine::general_purpose::STANDARD
+                    .decode(text.trim())
+                    .map_err(|e| format!("a protected value isn't valid base64: {e}"))?;
+                self.stream.apply(&mut value);
+                let value =
+                    String::from_utf8(value).map_err(|_| "a protected value isn't valid UTF-8")?;
+                self.value.push_str(&value);
+            }
+            ("String", "Value") => self.value.push_str(text),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        let name = self.elements.pop().ok_or("the XML is malformed")?;
+        let depth = self.elements.len();
+        match name.as_str() {
+            "Group" => {
+                self.groups.pop();
+            }
+            "Entry" => {
+                if let Some((_, fields)) = self.entry.take_if(|(at, _)| *at == depth) {
+                    self.entries.push(fields);
+                }
+            }
+            "String" => {
+                if let Some((at, fields)) = &mut self.entry {
+                    if *at + 1 == depth {
+                        let label = match self.key.as_str() {
+                            "Title" => "name",
+                            "UserName" => "username",
+                            "Password" => "password",
+                            "URL" if !self.value.is_empty() => "url",
+                            _ => return Ok(()),
+                        };
+                        fields.push((label.into(), std::mem::take(&mut self.value)));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err("the database is truncated".into());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(array(self.take(4)?)?))
+    }
+
+    /// Reads a 32-bit length and then that many bytes.
+    fn sized(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a header field, an ID and then its length and data.
+    fn field(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let id = self.take(1)?[0];
+        Ok((id, self.sized()?))
+    }
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> Result<[u8; N], String> {
+    bytes
+        .try_into()
+        .map_err(|_| "a header field is the wrong length".into())
+}
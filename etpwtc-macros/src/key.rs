@@ -0,0 +1,130 @@
+//! Keys and codes given to the macros, which needn't be written into the source.
+
+use crate::import;
+use proc_macro2::Span;
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    Error, Ident, LitByteStr, LitStr, Result,
+};
+
+/// `b"..."`, `env("VAR")` for the value of an environment variable at build time, or
+/// `file("PATH")` for the contents of a file relative to the invoking crate's
+/// manifest, minus one trailing newline. `file(env("VAR"))` takes the path from the
+/// environment instead. Paths are relative to `ETPWTC_BASE_DIR` instead when it's set,
+/// for source shared by crates in other directories; see `import::resolve`.
+///
+/// Cargo doesn't know that the macros read these, and they can't tell it without
+/// putting what they read into the expansion, where it would sit beside the sealed
+/// secrets. So the invoking crate's build script should print
+/// `cargo:rerun-if-env-changed=VAR` and `cargo:rerun-if-changed=PATH` for them, for it
+/// to be rebuilt when they change, as the firmware's does.
+pub(crate) enum KeySource {
+    Literal(LitByteStr),
+    Env(LitStr),
+    File(KeyPath),
+}
+
+pub(crate) enum KeyPath {
+    Literal(LitStr),
+    Env(LitStr),
+}
+
+impl Parse for KeySource {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(LitByteStr) {
+            return Ok(KeySource::Literal(input.parse()?));
+        }
+
+        let source: Ident = input.parse().map_err(|e| {
+            Error::new(
+                e.span(),
+                "expected `b\"...\"`, `env(\"VAR\")` or `file(\"PATH\")`",
+            )
+        })?;
+        let content;
+        parenthesized!(content in input);
+        if source == "env" {
+            Ok(KeySource::Env(content.parse()?))
+        } else if source == "file" {
+            if content.peek(LitStr) {
+                return Ok(KeySource::File(KeyPath::Literal(content.parse()?)));
+            }
+            let env: Ident = content.parse()?;
+            if env != "env" {
+                return Err(Error::new(
+                    env.span(),
+                    "expected `\"PATH\"` or `env(\"VAR\")`",
+                ));
+            }
+            let var;
+            parenthesized!(var in content);
+            Ok(KeySource::File(KeyPath::Env(var.parse()?)))
+        } else {
+            Err(Error::new(source.span(), "expected `env` or `file`"))
+        }
+    }
+}
+
+impl KeySource {
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            KeySource::Literal(bytes) => bytes.span(),
+            KeySource::Env(var) | KeySource::File(KeyPath::Env(var)) => var.span(),
+            KeySource::File(KeyPath::Literal(path)) => path.span(),
+        }
+    }
+
+    /// The bytes of the key.
+    pub(crate) fn resolve(&self) -> Result<Vec<u8>> {
+        let bytes = match self {
+            KeySource::Literal(bytes) => bytes.value(),
+            KeySource::Env(var) => env(var)?.into_bytes(),
+            KeySource::File(path) => {
+                let path = match path {
+                    KeyPath::Literal(path) => path.value(),
+                    KeyPath::Env(var) => env(var)?,
+                };
+                read(&path, self.span())?
+            }
+        };
+
+        if bytes.is_empty() {
+            return Err(Error::new(self.span(), "the key is empty"));
+        }
+        Ok(bytes)
+    }
+}
+
+fn env(var: &LitStr) -> Result<String> {
+    let name = var.value();
+    std::env::var(&name).map_err(|e| {
+        let problem = match e {
+            std::env::VarError::NotPresent => "isn't set",
+            std::env::VarError::NotUnicode(_) => "isn't valid UTF-8",
+        };
+        Error::new(
+            var.span(),
+            format!("environment variable `{name}` {problem}; it should be set at build time"),
+        )
+    })
+}
+
+/// Reads a key file, dropping the newline an editor or `echo` would leave at the end.
+fn read(path: &str, span: Span) -> Result<Vec<u8>> {
+    let resolved = import::resolve(path);
+    let mut bytes = std::fs::read(&resolved).map_err(|e| {
+        Error::new(
+            span,
+            format!("couldn't read key file `{}`: {e}", resolved.display()),
+        )
+    })?;
+    if bytes.ends_with(b"\n") {
+        bytes.pop();
+        if bytes.ends_with(b"\r") {
+            bytes.pop();
+        }
+    }
+
+    Ok(bytes)
+}
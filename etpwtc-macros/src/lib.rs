@@ -1,23 +1,425 @@
 extern crate proc_macro;
-use etpwtc_runtime::{Endec, Secret};
-use proc_macro2::{Group, Punct, Spacing, TokenStream};
-use quote::{quote, ToTokens, TokenStreamExt};
+mod bitwarden;
+mod csv;
+mod import;
+mod json;
+mod kdbx;
+mod key;
+
+use etpwtc_runtime::{
+    fill_image, random_key, Algorithm, Endec, EndecError, Field, Kdf, Key, SealedVault, Sealing,
+    Secret, KEY_SLOTS, MAX_CAPACITY,
+};
+use key::KeySource;
+use proc_macro2::{Group, Punct, Spacing, Span, TokenStream};
+use quote::{quote, quote_spanned, ToTokens, TokenStreamExt};
 use syn::{
+    braced, bracketed,
     parse::{Parse, ParseStream},
-    parse_macro_input, LitByteStr, Result, Token,
+    parse_macro_input,
+    punctuated::Punctuated,
+    Error, Ident, LitByteStr, LitInt, LitStr, Result, Token,
 };
 
 struct Encrypted {
-    key: LitByteStr,
-    plaintext: LitByteStr,
+    key: KeySource,
+    secrets: Secrets,
+    options: Options,
+}
+
+/// Trailing `name = value` settings, which apply to every secret in the invocation.
+struct Options {
+    kdf: Kdf,
+    algorithm: Algorithm,
+    capacity: usize,
+    padding: usize,
+    codes: Vec<KeySource>,
+    code_length: usize,
+    entries: Vec<Plaintext>,
+    version: u32,
+    image: Option<LitInt>,
+    group: Option<LitStr>,
+    kdbx_password: Option<KeySource>,
+    folder: Option<LitStr>,
+    tag: Option<LitStr>,
+    short_names: Option<import::ShortNames>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            kdf: Kdf::DEFAULT,
+            algorithm: Algorithm::DEFAULT,
+            capacity: 64,
+            padding: Endec::DEFAULT_BUCKET,
+            codes: Vec::new(),
+            code_length: 6,
+            entries: Vec::new(),
+            version: 1,
+            image: None,
+            group: None,
+            kdbx_password: None,
+            folder: None,
+            tag: None,
+            short_names: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the settings in `allowed`, separated by commas. When they follow the
+    /// macro's other arguments, the first is preceded by a comma too.
+    fn parse_allowing(input: ParseStream, allowed: &[&str], after_arguments: bool) -> Result<Self> {
+        let mut options = Options::default();
+        let mut comma = after_arguments;
+        while (!comma || input.parse::<Option<Token![,]>>()?.is_some()) && !input.is_empty() {
+            comma = true;
+            let name: Ident = input.parse()?;
+            if !allowed.iter().any(|allowed| name == allowed) {
+                let (last, rest) = allowed.split_last().unwrap();
+                let rest: Vec<_> = rest.iter().map(|name| format!("`{name}`")).collect();
+                return Err(Error::new(
+                    name.span(),
+                    format!("expected {} or `{last}`", rest.join(", ")),
+                ));
+            }
+
+            input.parse::<Token![=]>()?;
+            if name == "salt" {
+                let salt: LitByteStr = input.parse()?;
+                options.kdf.salt =
+                    salt.value().as_slice().try_into().map_err(|_| {
+                        Error::new(salt.span(), "salt must be exactly 16 bytes long")
+                    })?;
+            } else if name == "rounds" {
+                let rounds: LitInt = input.parse()?;
+                options.kdf.rounds = rounds.base10_parse()?;
+            } else if name == "algorithm" {
+                let algorithm: Ident = input.parse()?;
+                options.algorithm =
+                    Algorithm::from_label(&algorithm.to_string()).ok_or_else(|| {
+                        let labels: Vec<_> = Algorithm::ALL.iter().map(|a| a.label()).collect();
+                        Error::new(
+                            algorithm.span(),
+                            format!("unknown algorithm, expected one of {}", labels.join(", ")),
+                        )
+                    })?;
+            } else if name == "capacity" {
+                let capacity: LitInt = input.parse()?;
+                options.capacity = capacity.base10_parse()?;
+                if options.capacity > MAX_CAPACITY {
+                    return Err(Error::new(
+                        capacity.span(),
+                        format!("capacity is limited to {MAX_CAPACITY} bytes"),
+                    ));
+                }
+            } else if name == "padding" {
+                let padding: LitInt = input.parse()?;
+                options.padding = padding.base10_parse()?;
+            } else if name == "codes" {
+                let content;
+                let brackets = bracketed!(content in input);
+                options.codes = Punctuated::<KeySource, Token![,]>::parse_terminated(&content)?
+                    .into_iter()
+                    .collect();
+                if options.codes.is_empty() || options.codes.len() > KEY_SLOTS {
+                    return Err(Error::new(
+                        brackets.span.join(),
+                        format!("expected between 1 and {KEY_SLOTS} codes"),
+                    ));
+                }
+            } else if name == "code_length" {
+                let code_length: LitInt = input.parse()?;
+                options.code_length = code_length.base10_parse()?;
+            } else if name == "entries" {
+                let content;
+                bracketed!(content in input);
+                options.entries = Punctuated::<Plaintext, Token![,]>::parse_terminated(&content)?
+                    .into_iter()
+                    .collect();
+            } else if name == "image" {
+                options.image = Some(input.parse()?);
+            } else if name == "group" {
+                options.group = Some(input.parse()?);
+            } else if name == "kdbx_password" {
+                options.kdbx_password = Some(input.parse()?);
+            } else if name == "folder" {
+                options.folder = Some(input.parse()?);
+            } else if name == "tag" {
+                options.tag = Some(input.parse()?);
+            } else if name == "short_names" {
+                let how: Ident = input.parse()?;
+                options.short_names = Some(if how == "truncate" {
+                    import::ShortNames::Truncate
+                } else if how == "derive" {
+                    import::ShortNames::Derive
+                } else {
+                    return Err(Error::new(how.span(), "expected `truncate` or `derive`"));
+                });
+            } else {
+                let version: LitInt = input.parse()?;
+                options.version = version.base10_parse()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+impl Parse for Options {
+    fn parse(input: ParseStream) -> Result<Self> {
+        Options::parse_allowing(
+            input,
+            &["salt", "rounds", "algorithm", "capacity", "padding"],
+            true,
+        )
+    }
+}
+
+/// Either `KEY:LABEL, PLAINTEXT` for one secret or `KEY, [LABEL: PLAINTEXT, ...]`
+/// for an array, whose labels are checked against each other. Only labels within one
+/// array can be checked: each invocation expands on its own, so secrets sealed under
+/// the same key by separate invocations must be given distinct labels by hand. `KEY`
+/// can be a byte string or come from the environment or a file; see `KeySource`. A
+/// plaintext is either a byte string or an entry,
+/// `{ name: b"...", username: b"...", password: b"..." }`, and can be followed by
+/// `for [b"...", ...]` to bind it to data stored outside it.
+enum Secrets {
+    Single(Labelled),
+    Array(Vec<Labelled>),
+}
+
+struct Labelled {
+    context: LitInt,
+    plaintext: Plaintext,
+    bound: Vec<LitByteStr>,
+}
+
+#[derive(Clone)]
+enum Plaintext {
+    Bytes(LitByteStr),
+    Entry(Vec<(Field, LitByteStr)>),
+}
+
+impl Parse for Plaintext {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if !input.peek(syn::token::Brace) {
+            return Ok(Plaintext::Bytes(input.parse()?));
+        }
+
+        let content;
+        braced!(content in input);
+
+        let mut fields = Vec::new();
+        while !content.is_empty() {
+            let name: Ident = content.parse()?;
+            let field = Field::from_label(&name.to_string()).ok_or_else(|| {
+                Error::new(
+                    name.span(),
+                    "unknown field, expected `name`, `username` or `password`",
+                )
+            })?;
+            if fields.iter().any(|(f, _)| *f == field) {
+                return Err(Error::new(name.span(), format!("duplicate field `{name}`")));
+            }
+            content.parse::<Token![:]>()?;
+            fields.push((field, content.parse()?));
+
+            if content.parse::<Option<Token![,]>>()?.is_none() {
+                break;
+            }
+        }
+
+        Ok(Plaintext::Entry(fields))
+    }
+}
+
+impl Plaintext {
+    fn value(&self) -> Result<Vec<u8>> {
+        match self {
+            Plaintext::Bytes(bytes) => Ok(bytes.value()),
+            // encoded as `Entry::encode` would, but with no limit on the length, which is
+            // up to the capacity to check
+            Plaintext::Entry(fields) => {
+                let mut bytes = Vec::new();
+                for (field, literal) in fields {
+                    let value = literal.value();
+                    let len = u8::try_from(value.len()).map_err(|_| {
+                        Error::new(literal.span(), "fields are limited to 255 bytes")
+                    })?;
+                    bytes.extend_from_slice(&[*field as u8, len]);
+                    bytes.extend_from_slice(&value);
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Where to point when the plaintext as a whole is at fault: at the byte string, or
+    /// at an entry's longest field.
+    fn span(&self) -> Span {
+        match self {
+            Plaintext::Bytes(bytes) => bytes.span(),
+            Plaintext::Entry(fields) => fields
+                .iter()
+                .max_by_key(|(_, value)| value.value().len())
+                .map_or_else(Span::call_site, |(_, value)| value.span()),
+        }
+    }
+
+    /// Checks that everything the firmware types can be typed, which is an entry's
+    /// username and password. Byte strings are never typed, so they can hold anything.
+    fn check_typable(&self) -> Result<()> {
+        let Plaintext::Entry(fields) = self else {
+            return Ok(());
+        };
+        let typed = fields
+            .iter()
+            .filter(|(field, _)| matches!(field, Field::Username | Field::Password))
+            .map(|(_, value)| value);
+        for literal in typed {
+            if let Some(problem) = untypable(&literal.value()) {
+                return Err(Error::new(literal.span(), problem));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The firmware types on a US layout, which has keys for printable ASCII and nothing
+/// else; see `send_str` in its `usb.rs`. Describes the first character it can't type.
+fn untypable(bytes: &[u8]) -> Option<String> {
+    let at = bytes.iter().position(|b| !(b' '..=b'~').contains(b))?;
+    let c = String::from_utf8_lossy(&bytes[at..]).chars().next()?;
+    Some(format!(
+        "{c:?} can't be typed, since the keyboard layout only has printable ASCII"
+    ))
+}
+
+impl Labelled {
+    fn parse_plaintext(context: LitInt, input: ParseStream) -> Result<Self> {
+        let plaintext: Plaintext = input.parse()?;
+
+        let mut bound = Vec::new();
+        if input.parse::<Option<Token![for]>>()?.is_some() {
+            let content;
+            bracketed!(content in input);
+            bound.extend(Punctuated::<LitByteStr, Token![,]>::parse_terminated(
+                &content,
+            )?);
+        }
+
+        Ok(Labelled {
+            context,
+            plaintext,
+            bound,
+        })
+    }
+}
+
+impl Parse for Labelled {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let context: LitInt = input.parse()?;
+        input.parse::<Token![:]>()?;
+        Labelled::parse_plaintext(context, input)
+    }
 }
 
 impl Parse for Encrypted {
     fn parse(input: ParseStream) -> Result<Self> {
-        let key: LitByteStr = input.parse()?;
-        input.parse::<Token![,]>()?;
-        let plaintext: LitByteStr = input.parse()?;
-        Ok(Encrypted { key, plaintext })
+        let key: KeySource = input.parse()?;
+
+        let secrets = if input.peek(Token![:]) {
+            input.parse::<Token![:]>()?;
+            let context: LitInt = input.parse()?;
+            input.parse::<Token![,]>()?;
+            Secrets::Single(Labelled::parse_plaintext(context, input)?)
+        } else {
+            input.parse::<Token![,]>()?;
+            if !input.peek(syn::token::Bracket) {
+                return Err(input
+                    .error("expected `KEY:LABEL, PLAINTEXT` or `KEY, [LABEL: PLAINTEXT, ...]`"));
+            }
+            let content;
+            bracketed!(content in input);
+            let entries = Punctuated::<Labelled, Token![,]>::parse_terminated(&content)?;
+            Secrets::Array(entries.into_iter().collect())
+        };
+
+        let options: Options = input.parse()?;
+
+        Ok(Encrypted {
+            key,
+            secrets,
+            options,
+        })
+    }
+}
+
+/// `codes = [b"...", ...], entries = [PLAINTEXT, ...]`, optionally followed by
+/// `version = ...` and the same settings as `encrypted!`. Entries are sealed under a
+/// data key generated at random for each build, labelled with their index, and each
+/// code gets a slot holding that key. Codes can come from the environment or a file,
+/// like the key of `encrypted!`, and are checked to be `code_length = ...` presses of
+/// the buttons, 6 unless it's set to match the firmware's `CODE_LENGTH`.
+///
+/// With `image = SIZE`, the vault is emitted as a `&'static [u8; SIZE]` vault image
+/// rather than a `Vault`, placed in the `.vault` linker section on bare metal so that
+/// it can be replaced in a built firmware.
+struct VaultContents {
+    options: Options,
+}
+
+impl VaultContents {
+    const SETTINGS: [&'static str; 9] = [
+        "codes",
+        "code_length",
+        "version",
+        "image",
+        "salt",
+        "rounds",
+        "algorithm",
+        "capacity",
+        "padding",
+    ];
+
+    fn parse_allowing(input: ParseStream, allowed: &[&str], after_arguments: bool) -> Result<Self> {
+        let options = Options::parse_allowing(input, allowed, after_arguments)?;
+        if options.codes.is_empty() {
+            return Err(input.error("expected `codes = [...]`"));
+        }
+
+        Ok(VaultContents { options })
+    }
+}
+
+impl Parse for VaultContents {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut allowed = vec!["entries"];
+        allowed.extend(VaultContents::SETTINGS);
+        VaultContents::parse_allowing(input, &allowed, false)
+    }
+}
+
+/// `"PATH", codes = [...]` and the other settings of `vault!`, with the entries read
+/// from a file rather than written inline. A KeePass database also needs
+/// `group = "..."`, which is `""` for the root group, and `kdbx_password = ...`,
+/// which can come from the environment or a file like a code; Bitwarden and CSV
+/// exports can be narrowed down with `folder = "..."` or `tag = "..."`.
+/// `short_names = truncate` or `derive` fits long names onto the LCD. Entries too
+/// long for `capacity` are left out with a warning. See `import`.
+struct ImportedVault {
+    path: LitStr,
+    contents: VaultContents,
+}
+
+impl Parse for ImportedVault {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let path: LitStr = input.parse()?;
+        let mut allowed = vec!["group", "kdbx_password", "folder", "tag", "short_names"];
+        allowed.extend(VaultContents::SETTINGS);
+        let contents = VaultContents::parse_allowing(input, &allowed, true)?;
+        Ok(ImportedVault { path, contents })
     }
 }
 
@@ -35,38 +437,249 @@ impl ToTokens for ByteArray<'_> {
     }
 }
 
-static mut CONTEXT: u8 = 0;
-
 #[proc_macro]
 pub fn encrypted(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let Encrypted { key, plaintext } = parse_macro_input!(input as Encrypted);
+    let Encrypted {
+        key,
+        secrets,
+        options,
+    } = parse_macro_input!(input as Encrypted);
+    let key = match key.resolve() {
+        Ok(bytes) => options.kdf.derive(&bytes),
+        Err(e) => return e.into_compile_error().into(),
+    };
+
+    let output = match secrets {
+        Secrets::Single(secret) => {
+            seal(&key, &secret, &options).map(|secret| secret_tokens(&secret, options.capacity))
+        }
+        Secrets::Array(secrets) => {
+            let mut contexts = Vec::new();
+            secrets
+                .iter()
+                .map(|secret| {
+                    let context = secret.context.base10_parse::<u8>()?;
+                    if contexts.contains(&context) {
+                        return Err(Error::new(
+                            secret.context.span(),
+                            format!("duplicate context label `{context}`"),
+                        ));
+                    }
+                    contexts.push(context);
+                    let sealed = seal(&key, secret, &options)?;
+                    Ok(secret_tokens(&sealed, options.capacity))
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|secrets| quote! { [#(#secrets),*] })
+        }
+    };
+
+    output.unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[proc_macro]
+pub fn vault(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let VaultContents { options } = parse_macro_input!(input as VaultContents);
+    let output = seal_vault(options, TokenStream::new()).unwrap_or_else(Error::into_compile_error);
+    output.into()
+}
+
+/// Like `vault!`, but reads the entries from a file at compile time.
+#[proc_macro]
+pub fn encrypted_vault(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ImportedVault { path, contents } = parse_macro_input!(input as ImportedVault);
+    let VaultContents { mut options } = contents;
+
+    let output = import::load(&path, &options)
+        .and_then(|(entries, warnings)| {
+            options.entries = entries;
+            seal_vault(options, warnings)
+        })
+        .unwrap_or_else(Error::into_compile_error);
+    output.into()
+}
 
-    let mut endec = Endec::new(unsafe { CONTEXT });
-    unsafe {
-        CONTEXT += 1;
+fn seal_vault(options: Options, warnings: TokenStream) -> Result<TokenStream> {
+    if options.entries.len() > u8::MAX as usize + 1 {
+        return Err(Error::new(
+            Span::call_site(),
+            "vaults are limited to 256 entries",
+        ));
     }
 
+    let kdf = options.kdf;
+    let code_keys = options
+        .codes
+        .iter()
+        .map(|source| {
+            let code = source.resolve()?;
+            if code.len() != options.code_length || !code.iter().all(|b| b"abxy".contains(b)) {
+                return Err(Error::new(
+                    source.span(),
+                    format!(
+                        "unlock codes have to be {} presses of a, b, x or y",
+                        options.code_length
+                    ),
+                ));
+            }
+            Ok(kdf.derive(&code))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // like a vault `hwpw` creates, the data key is random rather than derived from
+    // anything in the source, so only the codes can open it
+    let data_key = random_key().map_err(|e| {
+        Error::new(
+            Span::call_site(),
+            format!("couldn't generate the data key: {e}"),
+        )
+    })?;
+
+    let entries = options
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, plaintext)| {
+            let labelled = Labelled {
+                context: LitInt::new(&i.to_string(), Span::call_site()),
+                plaintext: plaintext.clone(),
+                bound: Vec::new(),
+            };
+            seal(&data_key, &labelled, &options)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let first = &code_keys[0];
+    let mut vault =
+        SealedVault::new(kdf, options.algorithm, first, &data_key, options.version).unwrap();
+    for code_key in &code_keys[1..] {
+        vault.keyring.add_code(first, code_key).unwrap();
+    }
+    vault.replace(&data_key, entries).unwrap();
+    if let Some(size) = &options.image {
+        return image_tokens(&vault, size, warnings);
+    }
+
+    let Kdf { salt, rounds } = kdf;
+    let salt = ByteArray(&salt);
+    let slots = vault.keyring.slots.iter().map(|slot| match slot {
+        Some(secret) => {
+            let secret = secret_tokens(secret, secret.ciphertext.len());
+            quote! { Some(#secret) }
+        }
+        None => quote! { None },
+    });
+    let version = options.version;
+    let entries = vault
+        .entries
+        .iter()
+        .map(|entry| secret_tokens(entry, options.capacity));
+    let mac = ByteArray(&vault.keyring.mac);
+
+    Ok(quote! {{
+        #warnings
+        Vault {
+            kdf: Kdf {
+                salt: #salt,
+                rounds: #rounds
+            },
+            slots: [#(#slots),*],
+            version: #version,
+            entries: [#(#entries),*],
+            mac: #mac
+        }
+    }})
+}
+
+/// Emits the vault as an image of `size` bytes, in a static of its own since the
+/// firmware finds it by address.
+fn image_tokens(vault: &SealedVault, size: &LitInt, warnings: TokenStream) -> Result<TokenStream> {
+    let vault = vault.encode().unwrap();
+    let mut image = vec![0; size.base10_parse()?];
+    fill_image(&vault, &mut image).map_err(|_| {
+        Error::new(
+            size.span(),
+            format!(
+                "the vault is {} bytes, which doesn't fit an image of {} bytes with its \
+                 4 byte length",
+                vault.len(),
+                image.len()
+            ),
+        )
+    })?;
+    let size = image.len();
+    let image = ByteArray(&image);
+
+    Ok(quote! {{
+        #warnings
+        #[cfg_attr(target_os = "none", link_section = ".vault")]
+        #[used]
+        static IMAGE: [u8; #size] = #image;
+        &IMAGE
+    }})
+}
+
+fn seal(key: &Key, secret: &Labelled, options: &Options) -> Result<Secret<MAX_CAPACITY>> {
+    let context = secret.context.base10_parse::<u8>()?;
+    secret.plaintext.check_typable()?;
+    let plaintext = secret.plaintext.value()?;
+
+    let endec = secret
+        .bound
+        .iter()
+        .fold(Endec::new(context), |endec, data| endec.bind(&data.value()))
+        .algorithm(options.algorithm);
+    let sealing = Sealing {
+        capacity: options.capacity,
+        padding: options.padding,
+    };
+    etpwtc_runtime::seal(key, endec, &plaintext, sealing).map_err(|e| match e {
+        EndecError::InsufficientBufferCapacity => Error::new(
+            secret.plaintext.span(),
+            format!(
+                "this is {} bytes, too long to seal at a capacity of {} bytes, try a \
+                 larger `capacity`",
+                plaintext.len(),
+                options.capacity
+            ),
+        ),
+        e => Error::new(secret.context.span(), format!("couldn't seal: {e:?}")),
+    })
+}
+
+/// Emits `secret` as a literal, truncated to `capacity`.
+fn secret_tokens<const N: usize>(secret: &Secret<N>, capacity: usize) -> TokenStream {
     let Secret {
+        context,
+        algorithm,
         nonce,
         len,
         ciphertext,
-    } = endec
-        .enc::<64>(
-            &Endec::make_key(key.value().as_slice()),
-            plaintext.value().as_slice(),
-        )
-        .unwrap();
+    } = secret;
 
-    let nonce = ByteArray(&nonce);
-    let ciphertext = ByteArray(ciphertext.as_slice());
+    let algorithm = Ident::new(algorithm.label(), Span::call_site());
+    let nonce = ByteArray(nonce);
+    let ciphertext = ByteArray(&ciphertext[..capacity]);
 
-    let output = quote! {
+    quote! {
         Secret {
+            context: #context,
+            algorithm: Algorithm::#algorithm,
             nonce: #nonce,
             len: #len,
             ciphertext: #ciphertext
         }
-    };
+    }
+}
 
-    output.into()
+/// Emits an item which makes the compiler warn with `message` at `span`, since proc
+/// macros can't emit warnings of their own on stable.
+fn warning(span: Span, message: &str) -> TokenStream {
+    quote_spanned! {span=>
+        const _: () = {
+            #[deprecated(note = #message)]
+            struct Warning;
+            let _ = Warning;
+        };
+    }
 }
@@ -0,0 +1,226 @@
+use crate::{EndecError, Key, TAG_LEN};
+#[cfg(any(
+    feature = "chacha20poly1305",
+    feature = "xchacha20poly1305",
+    feature = "aes-gcm-siv"
+))]
+use aead::{generic_array::GenericArray, AeadMutInPlace, KeyInit};
+
+/// Nonces are stored at the largest size any algorithm needs, with the unused tail
+/// left as zeros.
+pub const MAX_NONCE_LEN: usize = 24;
+
+#[cfg(not(any(
+    feature = "chacha20poly1305",
+    feature = "xchacha20poly1305",
+    feature = "aes-gcm-siv"
+)))]
+compile_error!("at least one of the `chacha20poly1305`, `xchacha20poly1305` or `aes-gcm-siv` features must be enabled");
+
+/// The AEAD a secret was sealed with. These IDs are recorded alongside each secret,
+/// so every variant exists regardless of features; those whose feature is disabled
+/// fail with `UnsupportedAlgorithm`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Algorithm {
+    ChaCha20Poly1305 = 1,
+    XChaCha20Poly1305 = 2,
+    Aes256GcmSiv = 3,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [
+        Algorithm::ChaCha20Poly1305,
+        Algorithm::XChaCha20Poly1305,
+        Algorithm::Aes256GcmSiv,
+    ];
+
+    /// The first enabled algorithm, in order of preference.
+    #[cfg(feature = "chacha20poly1305")]
+    pub const DEFAULT: Algorithm = Algorithm::ChaCha20Poly1305;
+    #[cfg(all(not(feature = "chacha20poly1305"), feature = "xchacha20poly1305"))]
+    pub const DEFAULT: Algorithm = Algorithm::XChaCha20Poly1305;
+    #[cfg(all(
+        not(feature = "chacha20poly1305"),
+        not(feature = "xchacha20poly1305"),
+        feature = "aes-gcm-siv"
+    ))]
+    pub const DEFAULT: Algorithm = Algorithm::Aes256GcmSiv;
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Algorithm::ChaCha20Poly1305 => "ChaCha20Poly1305",
+            Algorithm::XChaCha20Poly1305 => "XChaCha20Poly1305",
+            Algorithm::Aes256GcmSiv => "Aes256GcmSiv",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Algorithm> {
+        Algorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.label() == label)
+    }
+
+    pub fn from_id(id: u8) -> Option<Algorithm> {
+        Algorithm::ALL
+            .into_iter()
+            .find(|algorithm| *algorithm as u8 == id)
+    }
+
+    pub fn nonce_len(self) -> usize {
+        match self {
+            Algorithm::ChaCha20Poly1305 => 12,
+            Algorithm::XChaCha20Poly1305 => 24,
+            Algorithm::Aes256GcmSiv => 12,
+        }
+    }
+
+    /// Whether this build was compiled with the algorithm's feature.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Algorithm::ChaCha20Poly1305 => cfg!(feature = "chacha20poly1305"),
+            Algorithm::XChaCha20Poly1305 => cfg!(feature = "xchacha20poly1305"),
+            Algorithm::Aes256GcmSiv => cfg!(feature = "aes-gcm-siv"),
+        }
+    }
+
+    pub(crate) fn seal(
+        self,
+        key: &Key,
+        nonce: &[u8],
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<[u8; TAG_LEN], EndecError> {
+        let nonce = &nonce[..self.nonce_len()];
+        match self {
+            #[cfg(feature = "chacha20poly1305")]
+            Algorithm::ChaCha20Poly1305 => {
+                chacha20poly1305::ChaCha20Poly1305::seal(key, nonce, associated_data, buffer)
+            }
+            #[cfg(feature = "xchacha20poly1305")]
+            Algorithm::XChaCha20Poly1305 => {
+                chacha20poly1305::XChaCha20Poly1305::seal(key, nonce, associated_data, buffer)
+            }
+            #[cfg(feature = "aes-gcm-siv")]
+            Algorithm::Aes256GcmSiv => {
+                aes_gcm_siv::Aes256GcmSiv::seal(key, nonce, associated_data, buffer)
+            }
+            #[cfg(not(all(
+                feature = "chacha20poly1305",
+                feature = "xchacha20poly1305",
+                feature = "aes-gcm-siv"
+            )))]
+            _ => Err(EndecError::UnsupportedAlgorithm),
+        }
+    }
+
+    pub(crate) fn open(
+        self,
+        key: &Key,
+        nonce: &[u8],
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), EndecError> {
+        let nonce = &nonce[..self.nonce_len()];
+        match self {
+            #[cfg(feature = "chacha20poly1305")]
+            Algorithm::ChaCha20Poly1305 => {
+                chacha20poly1305::ChaCha20Poly1305::open(key, nonce, associated_data, buffer, tag)
+            }
+            #[cfg(feature = "xchacha20poly1305")]
+            Algorithm::XChaCha20Poly1305 => {
+                chacha20poly1305::XChaCha20Poly1305::open(key, nonce, associated_data, buffer, tag)
+            }
+            #[cfg(feature = "aes-gcm-siv")]
+            Algorithm::Aes256GcmSiv => {
+                aes_gcm_siv::Aes256GcmSiv::open(key, nonce, associated_data, buffer, tag)
+            }
+            #[cfg(not(all(
+                feature = "chacha20poly1305",
+                feature = "xchacha20poly1305",
+                feature = "aes-gcm-siv"
+            )))]
+            _ => Err(EndecError::UnsupportedAlgorithm),
+        }
+    }
+}
+
+/// An AEAD backend. `Algorithm` dispatches to the implementations enabled by features.
+pub trait Cipher {
+    const ALGORITHM: Algorithm;
+
+    /// Encrypts `buffer` in place, returning the detached tag.
+    fn seal(
+        key: &Key,
+        nonce: &[u8],
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<[u8; TAG_LEN], EndecError>;
+
+    /// Decrypts `buffer` in place, failing if it doesn't match `tag`.
+    fn open(
+        key: &Key,
+        nonce: &[u8],
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), EndecError>;
+}
+
+#[cfg(any(
+    feature = "chacha20poly1305",
+    feature = "xchacha20poly1305",
+    feature = "aes-gcm-siv"
+))]
+macro_rules! aead_cipher {
+    ($ty:ty, $algorithm:ident) => {
+        impl Cipher for $ty {
+            const ALGORITHM: Algorithm = Algorithm::$algorithm;
+
+            fn seal(
+                key: &Key,
+                nonce: &[u8],
+                associated_data: &[u8],
+                buffer: &mut [u8],
+            ) -> Result<[u8; TAG_LEN], EndecError> {
+                let mut cipher = <$ty as KeyInit>::new_from_slice(key.as_bytes())
+                    .map_err(|_| EndecError::InvalidKeyLength)?;
+                let tag = cipher
+                    .encrypt_in_place_detached(
+                        GenericArray::from_slice(nonce),
+                        associated_data,
+                        buffer,
+                    )
+                    .map_err(|_| EndecError::InsufficientBufferCapacity)?;
+                Ok(tag.into())
+            }
+
+            fn open(
+                key: &Key,
+                nonce: &[u8],
+                associated_data: &[u8],
+                buffer: &mut [u8],
+                tag: &[u8],
+            ) -> Result<(), EndecError> {
+                let mut cipher = <$ty as KeyInit>::new_from_slice(key.as_bytes())
+                    .map_err(|_| EndecError::InvalidKeyLength)?;
+                cipher
+                    .decrypt_in_place_detached(
+                        GenericArray::from_slice(nonce),
+                        associated_data,
+                        buffer,
+                        GenericArray::from_slice(tag),
+                    )
+                    .map_err(|_| EndecError::DecryptionFailed)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "chacha20poly1305")]
+aead_cipher!(chacha20poly1305::ChaCha20Poly1305, ChaCha20Poly1305);
+#[cfg(feature = "xchacha20poly1305")]
+aead_cipher!(chacha20poly1305::XChaCha20Poly1305, XChaCha20Poly1305);
+#[cfg(feature = "aes-gcm-siv")]
+aead_cipher!(aes_gcm_siv::Aes256GcmSiv, Aes256GcmSiv);
@@ -0,0 +1,114 @@
+use crate::EndecError;
+use aead::heapless::Vec;
+
+/// Field tags used in an entry's plaintext. Tags not listed here are skipped when
+/// reading, so that entries with newer fields can still be opened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Field {
+    Name = 1,
+    Username = 2,
+    Password = 3,
+    /// Any other field, stored as its label, a zero byte and then its value.
+    Extra = 4,
+}
+
+impl Field {
+    /// The fields with labels of their own.
+    pub const ALL: [Field; 3] = [Field::Name, Field::Username, Field::Password];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Username => "username",
+            Field::Password => "password",
+            Field::Extra => "extra",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Field> {
+        Field::ALL.into_iter().find(|field| field.label() == label)
+    }
+}
+
+/// A decrypted entry, stored as a sequence of `tag, length, value` fields.
+#[derive(Clone, Copy)]
+pub struct Entry<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Checks that `bytes` is a well-formed sequence of fields.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, EndecError> {
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            match rest {
+                [_, len, value @ ..] if value.len() >= *len as usize => {
+                    rest = &value[*len as usize..];
+                }
+                _ => return Err(EndecError::MalformedEntry),
+            }
+        }
+
+        Ok(Entry { bytes })
+    }
+
+    pub fn encode<const N: usize>(fields: &[(Field, &[u8])]) -> Result<Vec<u8, N>, EndecError> {
+        let mut bytes = Vec::new();
+
+        for (field, value) in fields {
+            let len: u8 = value
+                .len()
+                .try_into()
+                .map_err(|_| EndecError::InsufficientBufferCapacity)?;
+
+            bytes
+                .extend_from_slice(&[*field as u8, len])
+                .and_then(|_| bytes.extend_from_slice(value))
+                .map_err(|_| EndecError::InsufficientBufferCapacity)?;
+        }
+
+        Ok(bytes)
+    }
+
+    /// All fields, including any with tags this version doesn't know about.
+    pub fn fields(&self) -> impl Iterator<Item = (u8, &'a [u8])> {
+        let mut rest = self.bytes;
+        core::iter::from_fn(move || match rest {
+            [tag, len, value @ ..] => {
+                let (value, next) = value.split_at(*len as usize);
+                rest = next;
+                Some((*tag, value))
+            }
+            _ => None,
+        })
+    }
+
+    pub fn get(&self, field: Field) -> Option<&'a [u8]> {
+        self.fields()
+            .find(|(tag, _)| *tag == field as u8)
+            .map(|(_, value)| value)
+    }
+
+    pub fn name(&self) -> &'a [u8] {
+        self.get(Field::Name).unwrap_or_default()
+    }
+
+    pub fn username(&self) -> &'a [u8] {
+        self.get(Field::Username).unwrap_or_default()
+    }
+
+    pub fn password(&self) -> &'a [u8] {
+        self.get(Field::Password).unwrap_or_default()
+    }
+
+    /// Extra fields, as `(label, value)` pairs.
+    pub fn extras(&self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        self.fields()
+            .filter(|(tag, _)| *tag == Field::Extra as u8)
+            .filter_map(|(_, value)| {
+                let split = value.iter().position(|b| *b == 0)?;
+                Some((&value[..split], &value[split + 1..]))
+            })
+    }
+}
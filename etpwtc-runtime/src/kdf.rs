@@ -0,0 +1,30 @@
+use crate::Key;
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+
+/// PBKDF2-HMAC-SHA256 parameters for turning an unlock code into a key. These
+/// are not secret, but must be the same at build time and at unlock time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kdf {
+    pub salt: [u8; 16],
+    pub rounds: u32,
+}
+
+impl Kdf {
+    /// Fallback parameters for secrets that don't specify their own. Builds
+    /// should use a salt of their own, since this one is public.
+    pub const DEFAULT: Kdf = Kdf {
+        salt: *b"etpwtc-kdf-salt!",
+        rounds: 4096,
+    };
+
+    pub fn derive(&self, code: &[u8]) -> Key {
+        let mut key = Key::from_bytes([0; 32]);
+        pbkdf2(code, &self.salt, self.rounds, key.as_mut_bytes());
+        key
+    }
+}
+
+pub(crate) fn pbkdf2(password: &[u8], salt: &[u8], rounds: u32, output: &mut [u8]) {
+    pbkdf2_hmac::<Sha256>(password, salt, rounds, output);
+}
@@ -0,0 +1,418 @@
+//! A log-structured key/value store for small values that change now and then, such
+//! as settings and counters, kept in a region of flash split into sectors.
+//!
+//! Each sector starts with a header and is then filled with records, each a new value
+//! for a key which supersedes any earlier one. Sectors are used in turn round a ring,
+//! so that erases are spread evenly across them. When the newest sector is full, the
+//! next one is started and the values still live in the oldest are copied into it,
+//! so that the oldest can be erased and one sector is always left free.
+//!
+//! Power can be lost at any step. Records are only counted once a commit byte after
+//! them is written, headers carry a checksum, and opening the store finishes moving
+//! on to a new sector if that was cut short. The oldest sector's header is cleared
+//! to zero once its values are copied, before it's erased, so that it's never copied
+//! again. A value being set when the power goes is either the old one or the new one
+//! afterwards. All integers are little-endian.
+//!
+//! A sector header is:
+//!
+//! | bytes | field                                              |
+//! |-------|----------------------------------------------------|
+//! | 4     | magic, `hwkv`                                      |
+//! | 4     | sequence number, one more than the previous sector |
+//! | 1     | CRC-8 of the magic and sequence number             |
+//!
+//! and a record:
+//!
+//! | bytes  | field                                  |
+//! |--------|----------------------------------------|
+//! | 1      | key, anything but `0xff`               |
+//! | 1      | length of the value                    |
+//! | length | value                                  |
+//! | 1      | CRC-8 of the key, length and value     |
+//! | 1      | `0x00`, written once the rest has been |
+
+use crate::Storage;
+
+/// Storage that can be written as NOR flash is: erased to `0xff` a sector at a time,
+/// after which writes can only clear bits.
+pub trait Flash: Storage {
+    const SECTOR_SIZE: usize;
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erases the sector starting at `offset`.
+    fn erase(&mut self, offset: usize) -> Result<(), Self::Error>;
+}
+
+impl<T: Flash> Flash for &mut T {
+    const SECTOR_SIZE: usize = T::SECTOR_SIZE;
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Self::Error> {
+        (**self).write(offset, bytes)
+    }
+
+    fn erase(&mut self, offset: usize) -> Result<(), Self::Error> {
+        (**self).erase(offset)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KvError<E> {
+    Flash(E),
+    /// The live values, with the new one, don't fit in a sector.
+    Full,
+    /// A value is longer than 255 bytes, than fits in a sector, or than the buffer
+    /// it's read into.
+    TooLong,
+    /// `0xff` is what erased flash reads as, so it can't be a key.
+    ReservedKey,
+    /// The store needs at least two sectors, one of them kept free.
+    TooSmall,
+}
+
+const MAGIC: [u8; 4] = *b"hwkv";
+const HEADER_LEN: usize = 9;
+const ERASED: u8 = 0xff;
+const COMMITTED: u8 = 0x00;
+
+pub struct KvStore<F> {
+    flash: F,
+    /// The sector records are added to, its sequence number, and where in it the next
+    /// record goes.
+    active: usize,
+    sequence: u32,
+    end: usize,
+}
+
+enum Sector {
+    Free,
+    Used(u32),
+    /// A header cut short by a power loss, either while being written or erased, or
+    /// cleared before the sector was erased.
+    Damaged,
+}
+
+/// A complete record, found at `pos` in its sector, with the next one at `next`.
+struct Record {
+    key: u8,
+    len: usize,
+    pos: usize,
+    next: usize,
+}
+
+impl<F: Flash> KvStore<F> {
+    /// Opens the store in `flash`, setting it up if it's erased and finishing anything
+    /// a power loss interrupted.
+    pub fn open(flash: F) -> Result<Self, KvError<F::Error>> {
+        let mut store = KvStore {
+            flash,
+            active: 0,
+            sequence: 0,
+            end: 0,
+        };
+        let sectors = store.sectors();
+        if sectors < 2 {
+            return Err(KvError::TooSmall);
+        }
+
+        let mut newest = None;
+        let mut free = 0;
+        for sector in 0..sectors {
+            match store.header(sector)? {
+                Sector::Free => free += 1,
+                Sector::Used(sequence) => {
+                    if newest.is_none_or(|(_, newest)| sequence > newest) {
+                        newest = Some((sector, sequence));
+                    }
+                }
+                Sector::Damaged => {
+                    store.erase(sector)?;
+                    free += 1;
+                }
+            }
+        }
+
+        match newest {
+            None => {
+                store.active = sectors - 1;
+                store.advance(None)?;
+            }
+            // moving on to the newest sector was cut short before the oldest was
+            // erased, so move on to it again, from scratch
+            Some((sector, sequence)) if free == 0 => {
+                store.active = (sector + sectors - 1) % sectors;
+                store.sequence = sequence.wrapping_sub(1);
+                store.advance(None)?;
+            }
+            Some((sector, sequence)) => {
+                store.active = sector;
+                store.sequence = sequence;
+                store.end = store.end_of(sector)?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Reads `key`'s value into `value`, returning its length, or `None` if it has
+    /// never been set.
+    pub fn get(&mut self, key: u8, value: &mut [u8]) -> Result<Option<usize>, KvError<F::Error>> {
+        let mut found = None;
+        let (active, sectors) = (self.active, self.sectors());
+        for sector in (1..=sectors).map(|i| (active + i) % sectors) {
+            if !matches!(self.header(sector)?, Sector::Used(_)) {
+                continue;
+            }
+            let mut pos = HEADER_LEN;
+            while let Some(record) = self.record_at(sector, pos)? {
+                pos = record.next;
+                if record.key == key {
+                    found = Some((sector, record));
+                }
+            }
+        }
+
+        let Some((sector, record)) = found else {
+            return Ok(None);
+        };
+        let out = value.get_mut(..record.len).ok_or(KvError::TooLong)?;
+        self.read(sector * F::SECTOR_SIZE + record.pos + 2, out)?;
+        Ok(Some(record.len))
+    }
+
+    /// Sets `key`'s value, moving on to the next sector if this one is full.
+    pub fn set(&mut self, key: u8, value: &[u8]) -> Result<(), KvError<F::Error>> {
+        if key == ERASED {
+            return Err(KvError::ReservedKey);
+        }
+        if value.len() > u8::MAX as usize || HEADER_LEN + value.len() + 4 > F::SECTOR_SIZE {
+            return Err(KvError::TooLong);
+        }
+
+        if self.end + value.len() + 4 <= F::SECTOR_SIZE {
+            self.append(key, value)
+        } else if self.advance(Some((key, value)))? {
+            Ok(())
+        } else {
+            Err(KvError::Full)
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Starts the next sector, copying in the values still live in the oldest so that
+    /// it can be erased, and adds `record` there if it fits. Returns whether it did.
+    fn advance(&mut self, record: Option<(u8, &[u8])>) -> Result<bool, KvError<F::Error>> {
+        let sectors = self.sectors();
+        let next = (self.active + 1) % sectors;
+        if !self.blank(next * F::SECTOR_SIZE, (next + 1) * F::SECTOR_SIZE)? {
+            self.erase(next)?;
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        header[8] = crc8(0, &header[..8]);
+        self.write(next * F::SECTOR_SIZE, &header)?;
+        self.active = next;
+        self.end = HEADER_LEN;
+
+        // a record for no key can't leave anything out
+        let (key, value) = record.unwrap_or((ERASED, &[]));
+        let oldest = (next + 1) % sectors;
+        let used = matches!(self.header(oldest)?, Sector::Used(_));
+        let fits = match used {
+            true => self.copy_live(oldest, key, value.len() + 4)?,
+            false => HEADER_LEN + value.len() + 4 <= F::SECTOR_SIZE,
+        };
+        if fits && record.is_some() {
+            self.append(key, value)?;
+        }
+        if used {
+            // an erase cut short can leave the header as it was, and the sector would
+            // then be copied again, with whatever of its records were erased missing
+            self.write(oldest * F::SECTOR_SIZE, &[0; HEADER_LEN])?;
+            self.erase(oldest)?;
+        }
+        Ok(fits)
+    }
+
+    /// Copies the values in `oldest` which haven't been superseded into the active
+    /// sector. `key`'s is left out if there's then room for `size` more bytes, which
+    /// is returned, since its new value is about to be added.
+    fn copy_live(
+        &mut self,
+        oldest: usize,
+        key: u8,
+        size: usize,
+    ) -> Result<bool, KvError<F::Error>> {
+        let sectors = self.sectors();
+        let mut newer = [false; 256];
+        for sector in (1..sectors).map(|i| (oldest + i) % sectors) {
+            if sector == self.active || !matches!(self.header(sector)?, Sector::Used(_)) {
+                continue;
+            }
+            let mut pos = HEADER_LEN;
+            while let Some(record) = self.record_at(sector, pos)? {
+                newer[record.key as usize] = true;
+                pos = record.next;
+            }
+        }
+
+        // each key's last record in `oldest`, which is live unless there's a newer one
+        let mut last = [0; 256];
+        let mut pos = HEADER_LEN;
+        while let Some(record) = self.record_at(oldest, pos)? {
+            last[record.key as usize] = pos;
+            pos = record.next;
+        }
+        let live = |record: &Record| {
+            !newer[record.key as usize] && last[record.key as usize] == record.pos
+        };
+
+        let mut needed = 0;
+        let mut pos = HEADER_LEN;
+        while let Some(record) = self.record_at(oldest, pos)? {
+            if live(&record) && record.key != key {
+                needed += record.next - record.pos;
+            }
+            pos = record.next;
+        }
+        let fits = self.end + needed + size <= F::SECTOR_SIZE;
+
+        let mut value = [0; u8::MAX as usize];
+        let mut pos = HEADER_LEN;
+        while let Some(record) = self.record_at(oldest, pos)? {
+            if live(&record) && !(fits && record.key == key) {
+                let value = &mut value[..record.len];
+                self.read(oldest * F::SECTOR_SIZE + record.pos + 2, value)?;
+                self.append(record.key, value)?;
+            }
+            pos = record.next;
+        }
+        Ok(fits)
+    }
+
+    /// Adds a record to the active sector, which has room for it, committing it once
+    /// the rest is written.
+    fn append(&mut self, key: u8, value: &[u8]) -> Result<(), KvError<F::Error>> {
+        let offset = self.active * F::SECTOR_SIZE + self.end;
+        let start = [key, value.len() as u8];
+        let crc = crc8(crc8(0, &start), value);
+        self.write(offset, &start)?;
+        self.write(offset + 2, value)?;
+        self.write(offset + 2 + value.len(), &[crc])?;
+        self.write(offset + 3 + value.len(), &[COMMITTED])?;
+        self.end += value.len() + 4;
+        Ok(())
+    }
+
+    fn header(&mut self, sector: usize) -> Result<Sector, KvError<F::Error>> {
+        let mut header = [0; HEADER_LEN];
+        self.read(sector * F::SECTOR_SIZE, &mut header)?;
+        Ok(if header.iter().all(|b| *b == ERASED) {
+            Sector::Free
+        } else if header[..4] == MAGIC && header[8] == crc8(0, &header[..8]) {
+            Sector::Used(u32::from_le_bytes(header[4..8].try_into().unwrap()))
+        } else {
+            Sector::Damaged
+        })
+    }
+
+    /// The complete record at `pos` in `sector`, if there is one.
+    fn record_at(
+        &mut self,
+        sector: usize,
+        pos: usize,
+    ) -> Result<Option<Record>, KvError<F::Error>> {
+        if pos + 4 > F::SECTOR_SIZE {
+            return Ok(None);
+        }
+        let offset = sector * F::SECTOR_SIZE + pos;
+        let mut start = [0; 2];
+        self.read(offset, &mut start)?;
+        let [key, len] = start;
+        let next = pos + len as usize + 4;
+        if key == ERASED || next > F::SECTOR_SIZE {
+            return Ok(None);
+        }
+
+        let mut value = [0; u8::MAX as usize];
+        let value = &mut value[..len as usize];
+        let mut end = [0; 2];
+        self.read(offset + 2, value)?;
+        self.read(offset + 2 + value.len(), &mut end)?;
+        let [crc, commit] = end;
+        if commit != COMMITTED || crc != crc8(crc8(0, &start), value) {
+            return Ok(None);
+        }
+        Ok(Some(Record {
+            key,
+            len: len as usize,
+            pos,
+            next,
+        }))
+    }
+
+    /// Where the next record goes in `sector`: after the last complete one, unless
+    /// something was cut short after that, in which case the sector is finished with.
+    fn end_of(&mut self, sector: usize) -> Result<usize, KvError<F::Error>> {
+        let mut pos = HEADER_LEN;
+        while let Some(record) = self.record_at(sector, pos)? {
+            pos = record.next;
+        }
+        let start = sector * F::SECTOR_SIZE;
+        match self.blank(start + pos, start + F::SECTOR_SIZE)? {
+            true => Ok(pos),
+            false => Ok(F::SECTOR_SIZE),
+        }
+    }
+
+    fn blank(&mut self, from: usize, to: usize) -> Result<bool, KvError<F::Error>> {
+        let mut chunk = [0; 32];
+        let mut offset = from;
+        while offset < to {
+            let chunk = &mut chunk[..(to - offset).min(32)];
+            self.read(offset, chunk)?;
+            if chunk.iter().any(|b| *b != ERASED) {
+                return Ok(false);
+            }
+            offset += chunk.len();
+        }
+        Ok(true)
+    }
+
+    fn sectors(&self) -> usize {
+        self.flash.size() / F::SECTOR_SIZE
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), KvError<F::Error>> {
+        self.flash.read(offset, bytes).map_err(KvError::Flash)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), KvError<F::Error>> {
+        self.flash.write(offset, bytes).map_err(KvError::Flash)
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), KvError<F::Error>> {
+        self.flash
+            .erase(sector * F::SECTOR_SIZE)
+            .map_err(KvError::Flash)
+    }
+}
+
+/// CRC-8 with the polynomial 0x07, continuing from `crc`.
+fn crc8(mut crc: u8, bytes: &[u8]) -> u8 {
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = match crc & 0x80 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x07,
+            };
+        }
+    }
+    crc
+}
@@ -1,13 +1,31 @@
 #![no_std]
 
+mod cipher;
+mod entry;
+mod kdf;
+mod kv;
+#[cfg(all(feature = "std", not(target_os = "none")))]
+mod seal;
+mod sensitive;
+mod storage;
 #[cfg(test)]
 mod tests;
+mod vault;
+mod wire;
 
-pub use chacha20poly1305::aead::heapless;
-use chacha20poly1305::{
-    aead::{heapless::Vec, AeadMutInPlace, KeyInit},
-    ChaCha20Poly1305, Nonce,
-};
+pub use aead::heapless;
+pub use cipher::{Algorithm, Cipher, MAX_NONCE_LEN};
+pub use entry::{Entry, Field};
+use hmac::{Hmac, Mac};
+pub use kdf::Kdf;
+pub use kv::{Flash, KvError, KvStore};
+#[cfg(all(feature = "std", not(target_os = "none")))]
+pub use seal::{random_key, seal, SealedVault, Sealing, MAX_CAPACITY};
+pub use sensitive::{Key, Plaintext};
+use sha2::{Digest, Sha256};
+pub use storage::{load, LoadError, Storage};
+pub use vault::{entries_mac, StoredVault, Vault, KEY_SLOTS};
+pub use wire::{fill_image, image_contents, FORMAT_VERSION, VAULT_MAGIC};
 
 #[derive(Debug, PartialEq)]
 pub enum EndecError {
@@ -15,98 +33,241 @@ pub enum EndecError {
     InsufficientBufferCapacity,
     IncorrectArraySize,
     DecryptionFailed,
+    MalformedEntry,
+    InvalidPadding,
+    IncorrectKey,
+    NoFreeSlot,
+    UnsupportedAlgorithm,
+    Truncated,
+    TrailingBytes,
+    BadMagic,
+    UnsupportedVersion,
+    InvalidLength,
+    InvalidKdf,
+    IntegrityCheckFailed,
+    Rollback,
+    /// A vault holds more entries than there's room for where it's loaded.
+    TooManyEntries,
 }
 
+/// Every sealed secret ends in an authentication tag of this many bytes, so a
+/// plaintext must be shorter than its capacity less this.
+pub const TAG_LEN: usize = 16;
+
 pub struct Endec {
-    context: u8,       // prevents code replay,
-    counter: [u8; 12], // generates unique nonces
+    context: u8,                  // prevents code replay,
+    algorithm: Algorithm,         // chooses the AEAD for sealing
+    counter: [u8; MAX_NONCE_LEN], // generates unique nonces
+    binding: Option<Sha256>,      // ties the secret to data stored beside it
+    bucket: usize,                // hides plaintext lengths
 }
 
 #[derive(Clone)]
 pub struct Secret<const N: usize> {
-    pub nonce: [u8; 12],
+    pub context: u8,
+    pub algorithm: Algorithm,
+    pub nonce: [u8; MAX_NONCE_LEN],
     pub len: usize,
     pub ciphertext: [u8; N],
 }
 
 impl Endec {
-    pub fn make_key(bytes: &[u8]) -> [u8; 32] {
-        let mut key = [0; 32];
+    pub const DEFAULT_BUCKET: usize = 16;
 
-        for i in 0..32 {
-            key[i] = bytes[i % bytes.len()];
+    pub fn new(associated_data: u8) -> Self {
+        Endec {
+            context: associated_data,
+            algorithm: Algorithm::DEFAULT,
+            counter: [0; MAX_NONCE_LEN],
+            binding: None,
+            bucket: Self::DEFAULT_BUCKET,
         }
-
-        key
     }
 
-    pub fn new(associated_data: u8) -> Self {
+    /// Starts counting from `nonce` rather than zero, so that separate instances
+    /// sharing a key can be given disjoint nonce sequences.
+    pub fn with_nonce(associated_data: u8, nonce: [u8; MAX_NONCE_LEN]) -> Self {
         Endec {
             context: associated_data,
-            counter: [0; 12],
+            algorithm: Algorithm::DEFAULT,
+            counter: nonce,
+            binding: None,
+            bucket: Self::DEFAULT_BUCKET,
+        }
+    }
+
+    /// Seals with `algorithm` rather than the default. Decryption always uses the
+    /// algorithm recorded in the secret.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Pads plaintexts to a multiple of `bucket` bytes before sealing (or to the
+    /// capacity, if that is smaller), so that a ciphertext's length only reveals
+    /// which bucket the plaintext falls into.
+    pub fn pad_to(mut self, bucket: usize) -> Self {
+        self.bucket = bucket.max(1);
+        self
+    }
+
+    /// Authenticates `data` as part of the associated data. It isn't encrypted, but
+    /// decryption will fail unless exactly the same data is bound, in the same order.
+    pub fn bind(mut self, data: &[u8]) -> Self {
+        let binding = self.binding.get_or_insert_with(Sha256::new);
+        binding.update((data.len() as u32).to_le_bytes());
+        binding.update(data);
+        self
+    }
+
+    /// Derives the starting nonce from the key, associated data and plaintext, SIV-style,
+    /// so that sealing the same inputs is reproducible but any change to them (such as
+    /// editing a password) produces a different nonce. Call this after binding.
+    pub fn synthetic(mut self, key: &Key, plaintext: &[u8]) -> Self {
+        let mut buffer = [0; 33];
+        let associated_data = self.associated_data(&mut buffer);
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(&[associated_data.len() as u8]);
+        mac.update(associated_data);
+        mac.update(plaintext);
+        let digest = mac.finalize().into_bytes();
+
+        self.counter.copy_from_slice(&digest[..MAX_NONCE_LEN]);
+        self
+    }
+
+    fn associated_data<'a>(&self, buffer: &'a mut [u8; 33]) -> &'a [u8] {
+        buffer[0] = self.context;
+        match &self.binding {
+            None => &buffer[..1],
+            Some(binding) => {
+                buffer[1..].copy_from_slice(&binding.clone().finalize());
+                &buffer[..]
+            }
         }
     }
 
     pub fn enc<const N: usize>(
         &mut self,
-        key: &[u8; 32],
+        key: &Key,
         plaintext: &[u8],
     ) -> Result<Secret<N>, EndecError> {
-        let nonce = Nonce::clone_from_slice(&self.counter);
-
-        let mut scratch: Vec<u8, N> =
-            Vec::from_slice(plaintext).map_err(|_| EndecError::InsufficientBufferCapacity)?;
+        let mut ciphertext = [0; N];
+        let (nonce, len) = self.enc_into(key, plaintext, &mut ciphertext)?;
 
-        let mut cipher =
-            ChaCha20Poly1305::new_from_slice(key).map_err(|_| EndecError::InvalidKeyLength)?;
+        Ok(Secret {
+            context: self.context,
+            algorithm: self.algorithm,
+            nonce,
+            len,
+            ciphertext,
+        })
+    }
 
-        cipher
-            .encrypt_in_place(&nonce, &[self.context], &mut scratch)
-            .map_err(|_| EndecError::InsufficientBufferCapacity)?;
+    /// Seals into a buffer whose capacity needn't be known at compile time, returning
+    /// the nonce used and the number of bytes written, including padding and tag.
+    pub fn enc_into(
+        &mut self,
+        key: &Key,
+        plaintext: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<([u8; MAX_NONCE_LEN], usize), EndecError> {
+        if !self.algorithm.is_supported() {
+            return Err(EndecError::UnsupportedAlgorithm);
+        }
 
-        let len = scratch.len();
-        unsafe {
-            scratch.set_len(N);
+        // ISO/IEC 7816-4 padding: a 0x80 marker, then zeros up to the bucket boundary
+        let capacity = buffer.len().saturating_sub(TAG_LEN);
+        if plaintext.len() >= capacity {
+            return Err(EndecError::InsufficientBufferCapacity);
         }
+        let padded = (plaintext.len() + 1)
+            .next_multiple_of(self.bucket)
+            .min(capacity);
 
-        let message = Secret {
-            nonce: self.counter.clone(),
-            len,
-            ciphertext: scratch
-                .into_array()
-                .map_err(|_| EndecError::IncorrectArraySize)?,
-        };
-        increment_nonce(&mut self.counter);
+        buffer.fill(0);
+        buffer[..plaintext.len()].copy_from_slice(plaintext);
+        buffer[plaintext.len()] = 0x80;
+
+        let nonce_len = self.algorithm.nonce_len();
+        let mut nonce = [0; MAX_NONCE_LEN];
+        nonce[..nonce_len].copy_from_slice(&self.counter[..nonce_len]);
 
-        Ok(message)
+        let mut associated_data = [0; 33];
+        let tag = self.algorithm.seal(
+            key,
+            &nonce,
+            self.associated_data(&mut associated_data),
+            &mut buffer[..padded],
+        )?;
+        buffer[padded..padded + TAG_LEN].copy_from_slice(&tag);
+
+        increment_nonce(&mut self.counter[..nonce_len]);
+
+        Ok((nonce, padded + TAG_LEN))
     }
 
     pub fn dec<const N: usize>(
         &mut self,
-        key: &[u8; 32],
+        key: &Key,
         message: &Secret<N>,
-    ) -> Result<Vec<u8, N>, EndecError> {
-        let nonce = Nonce::clone_from_slice(&message.nonce);
+    ) -> Result<Plaintext<N>, EndecError> {
+        let sealed = message
+            .ciphertext
+            .get(..message.len)
+            .ok_or(EndecError::DecryptionFailed)?;
+        let body_len = sealed
+            .len()
+            .checked_sub(TAG_LEN)
+            .ok_or(EndecError::DecryptionFailed)?;
 
-        let mut scratch = Vec::from_slice(&message.ciphertext)
-            .map_err(|_| EndecError::InsufficientBufferCapacity)?;
-        scratch.truncate(message.len);
+        if !message.algorithm.is_supported() {
+            return Err(EndecError::UnsupportedAlgorithm);
+        }
 
-        let mut cipher =
-            ChaCha20Poly1305::new_from_slice(key).map_err(|_| EndecError::InvalidKeyLength)?;
+        let mut scratch = Plaintext::from_slice(&sealed[..body_len])?;
 
-        cipher
-            .decrypt_in_place(&nonce, &[self.context], &mut scratch)
-            .map_err(|_| EndecError::DecryptionFailed)?;
+        let mut associated_data = [0; 33];
+        message.algorithm.open(
+            key,
+            &message.nonce,
+            self.associated_data(&mut associated_data),
+            scratch.as_mut_slice(),
+            &sealed[body_len..],
+        )?;
+
+        match scratch.iter().rposition(|b| *b != 0) {
+            Some(marker) if scratch[marker] == 0x80 => {
+                scratch.truncate(marker);
+                Ok(scratch)
+            }
+            _ => Err(EndecError::InvalidPadding),
+        }
+    }
+}
+
+impl<const N: usize> Secret<N> {
+    /// Decrypts using the context this secret was sealed with.
+    pub fn open(&self, key: &Key) -> Result<Plaintext<N>, EndecError> {
+        Endec::new(self.context).dec(key, self)
+    }
 
-        Ok(scratch)
+    /// Decrypts a secret that was sealed with `bound` data, such as the name and
+    /// username of its entry.
+    pub fn open_bound(&self, key: &Key, bound: &[&[u8]]) -> Result<Plaintext<N>, EndecError> {
+        bound
+            .iter()
+            .fold(Endec::new(self.context), |endec, data| endec.bind(data))
+            .dec(key, self)
     }
 }
 
-fn increment_nonce<const N: usize>(slice: &mut [u8; N]) {
+fn increment_nonce(slice: &mut [u8]) {
     let mut i = 0usize;
     let mut carry = 1u64;
-    while carry > 0 && i < N {
+    while carry > 0 && i < slice.len() {
         carry += slice[i] as u64;
         slice[i] = carry as u8;
         carry /= 256;
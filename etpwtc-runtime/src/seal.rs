@@ -0,0 +1,115 @@
+//! Sealing vaults on the host, which the macros and `hwpw` both do and have to agree
+//! on for the firmware to open what either of them makes.
+
+extern crate std;
+
+use crate::{entries_mac, Algorithm, Endec, EndecError, Kdf, Key, Secret, Vault, MAX_NONCE_LEN};
+use std::{vec, vec::Vec};
+
+/// Entries are sealed at this size whatever capacity they're emitted at, which is the
+/// largest either tool allows.
+pub const MAX_CAPACITY: usize = 4096;
+
+/// How entries are sealed, which has to suit the firmware they'll be built into.
+#[derive(Clone, Copy)]
+pub struct Sealing {
+    pub capacity: usize,
+    pub padding: usize,
+}
+
+/// A random data key, which only the codes wrapping it can recover, since nothing in
+/// the inputs it's sealed from can derive it.
+pub fn random_key() -> Result<Key, getrandom::Error> {
+    let mut bytes = [0; 32];
+    getrandom::fill(&mut bytes)?;
+    Ok(Key::from_bytes(bytes))
+}
+
+/// Seals `plaintext` with whatever context, algorithm and bindings `endec` was given.
+/// Nonces are derived from the inputs rather than drawn at random, so that sealing is
+/// reproducible but no two secrets share a nonce.
+pub fn seal(
+    key: &Key,
+    endec: Endec,
+    plaintext: &[u8],
+    sealing: Sealing,
+) -> Result<Secret<MAX_CAPACITY>, EndecError> {
+    let mut endec = endec.synthetic(key, plaintext).pad_to(sealing.padding);
+    let mut sealed = Secret {
+        context: endec.context,
+        algorithm: endec.algorithm,
+        nonce: [0; MAX_NONCE_LEN],
+        len: 0,
+        ciphertext: [0; MAX_CAPACITY],
+    };
+    let buffer = sealed
+        .ciphertext
+        .get_mut(..sealing.capacity)
+        .ok_or(EndecError::InsufficientBufferCapacity)?;
+    (sealed.nonce, sealed.len) = endec.enc_into(key, plaintext, buffer)?;
+    Ok(sealed)
+}
+
+/// A vault with however many entries, as it's built and edited on the host.
+pub struct SealedVault {
+    /// Everything but the entries, which an empty vault can hold.
+    pub keyring: Vault<MAX_CAPACITY, 0>,
+    pub entries: Vec<Secret<MAX_CAPACITY>>,
+}
+
+impl SealedVault {
+    /// Creates an empty vault which opens with `code_key`. More codes can be added to
+    /// `keyring` as to any vault.
+    pub fn new(
+        kdf: Kdf,
+        algorithm: Algorithm,
+        code_key: &Key,
+        data_key: &Key,
+        version: u32,
+    ) -> Result<SealedVault, EndecError> {
+        Ok(SealedVault {
+            keyring: Vault::new(kdf, algorithm, code_key, data_key, version, [])?,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SealedVault, EndecError> {
+        let mut entries = Vec::new();
+        let keyring = Vault::decode_with(bytes, |entry| entries.push(entry))?;
+        Ok(SealedVault { keyring, entries })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, EndecError> {
+        let len = self.keyring.encoded_len()
+            + self.entries.iter().map(Secret::encoded_len).sum::<usize>();
+        let mut bytes = vec![0; len];
+        self.keyring.encode_with(&self.entries, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Replaces the entries with `entries`, which must be sealed under `data_key` and
+    /// labelled with their indices, and covers them with a MAC at the current version.
+    pub fn replace(
+        &mut self,
+        data_key: &Key,
+        entries: Vec<Secret<MAX_CAPACITY>>,
+    ) -> Result<(), EndecError> {
+        // contexts are a byte, so more entries would have to share them
+        if entries.len() > u8::MAX as usize + 1 {
+            return Err(EndecError::TooManyEntries);
+        }
+        self.keyring.mac = entries_mac(data_key, self.keyring.version, &entries);
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// Entries are sealed with the same algorithm as the first slot.
+    pub fn algorithm(&self) -> Algorithm {
+        self.keyring
+            .slots
+            .iter()
+            .flatten()
+            .next()
+            .map_or(Algorithm::DEFAULT, |slot| slot.algorithm)
+    }
+}
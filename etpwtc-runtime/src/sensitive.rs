@@ -0,0 +1,104 @@
+use crate::EndecError;
+use core::{fmt, ops::Deref};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// A 256-bit key, wiped when dropped.
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Key(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub(crate) fn as_mut_bytes(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+
+    /// Compares without branching on the contents, so timing reveals nothing.
+    pub fn ct_eq(&self, other: &Key) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// Decrypted data, wiped when dropped. This deliberately isn't `Clone`; copies
+/// have to be made explicitly with `from_slice`.
+pub struct Plaintext<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Plaintext<N> {
+    pub fn from_slice(slice: &[u8]) -> Result<Self, EndecError> {
+        let mut plaintext = Plaintext {
+            bytes: [0; N],
+            len: slice.len(),
+        };
+
+        plaintext
+            .bytes
+            .get_mut(..slice.len())
+            .ok_or(EndecError::InsufficientBufferCapacity)?
+            .copy_from_slice(slice);
+
+        Ok(plaintext)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.bytes[len..self.len].zeroize();
+            self.len = len;
+        }
+    }
+
+    /// Compares without branching on the contents, so timing reveals nothing
+    /// except the length.
+    pub fn ct_eq(&self, other: &[u8]) -> bool {
+        self.as_slice().ct_eq(other).into()
+    }
+}
+
+impl<const N: usize> Deref for Plaintext<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> Drop for Plaintext<N> {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> fmt::Debug for Plaintext<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Plaintext(..)")
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{image_contents, EndecError, StoredVault};
+
+/// A region holding a vault image, such as the part of flash set aside for it, read
+/// with offsets from the start of the region.
+pub trait Storage {
+    type Error;
+
+    /// The size of the region, which the image fills.
+    fn size(&self) -> usize;
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+impl<T: Storage> Storage for &mut T {
+    type Error = T::Error;
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        (**self).read(offset, bytes)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError<E> {
+    /// The region is erased, so no vault has been written to it.
+    Empty,
+    Storage(E),
+    /// The region holds something other than a vault this firmware can use.
+    Invalid(EndecError),
+}
+
+/// Reads the vault image from `storage` and decodes it, with up to `MAX` entries.
+/// `buffer` holds the image meanwhile, so it has to be at least as large as the region.
+pub fn load<const N: usize, const MAX: usize, S: Storage>(
+    storage: &mut S,
+    buffer: &mut [u8],
+) -> Result<StoredVault<N, MAX>, LoadError<S::Error>> {
+    let image = buffer
+        .get_mut(..storage.size())
+        .ok_or(LoadError::Invalid(EndecError::InsufficientBufferCapacity))?;
+    storage.read(0, image).map_err(LoadError::Storage)?;
+
+    if image.iter().take(4).all(|b| *b == 0xff) {
+        return Err(LoadError::Empty);
+    }
+    image_contents(image)
+        .and_then(StoredVault::decode)
+        .map_err(LoadError::Invalid)
+}
@@ -1,4 +1,14 @@
-use crate::{Endec, EndecError};
+use crate::{
+    entries_mac, fill_image, heapless, image_contents, kdf::pbkdf2, load, Algorithm, Endec,
+    EndecError, Entry, Field, Flash, Kdf, Key, KvError, KvStore, LoadError, Plaintext, Secret,
+    Storage, Vault,
+};
+
+const KEY: Key = Key::from_bytes(*b"01234567890123456789012345678901");
+const OTHER_KEY: Key = Key::from_bytes(*b"0123456789012345678901234567890x");
+
+/// A vault without entries, for testing key slots
+type Keyring = Vault<32, 0>;
 
 #[test]
 fn roundtrip_same_instance() {
@@ -6,15 +16,11 @@ fn roundtrip_same_instance() {
 
     let plaintext = b"secret";
 
-    let message = endec
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
-        .unwrap();
+    let message = endec.enc::<32>(&KEY, plaintext).unwrap();
 
-    let replaintext = endec
-        .dec(b"01234567890123456789012345678901", &message)
-        .unwrap();
+    let replaintext = endec.dec(&KEY, &message).unwrap();
 
-    assert_eq!(plaintext, replaintext);
+    assert_eq!(plaintext, replaintext.as_slice());
 }
 
 #[test]
@@ -23,17 +29,28 @@ fn roundtrip_separate_instances() {
 
     let plaintext = b"secret";
 
-    let message = endec1
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
-        .unwrap();
+    let message = endec1.enc::<32>(&KEY, plaintext).unwrap();
 
     let mut endec2 = Endec::new(0);
 
-    let replaintext = endec2
-        .dec(b"01234567890123456789012345678901", &message)
-        .unwrap();
+    let replaintext = endec2.dec(&KEY, &message).unwrap();
 
-    assert_eq!(plaintext, replaintext);
+    assert_eq!(plaintext, replaintext.as_slice());
+}
+
+#[test]
+fn open_uses_own_context() {
+    let mut endec = Endec::new(5);
+
+    let plaintext = b"secret";
+
+    let message = endec.enc::<32>(&KEY, plaintext).unwrap();
+
+    assert_eq!(5, message.context);
+
+    let replaintext = message.open(&KEY).unwrap();
+
+    assert_eq!(plaintext, replaintext.as_slice());
 }
 
 #[test]
@@ -42,15 +59,11 @@ fn incorrect_tag() {
 
     let plaintext = b"secret";
 
-    let message = endec1
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
-        .unwrap();
+    let message = endec1.enc::<32>(&KEY, plaintext).unwrap();
 
     let mut endec2 = Endec::new(1);
 
-    let err = endec2
-        .dec(b"01234567890123456789012345678901", &message)
-        .unwrap_err();
+    let err = endec2.dec(&KEY, &message).unwrap_err();
 
     assert_eq!(EndecError::DecryptionFailed, err);
 }
@@ -61,15 +74,11 @@ fn incorrect_key() {
 
     let plaintext = b"secret";
 
-    let message = endec1
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
-        .unwrap();
+    let message = endec1.enc::<32>(&KEY, plaintext).unwrap();
 
     let mut endec2 = Endec::new(0);
 
-    let err = endec2
-        .dec(b"0123456789012345678901234567890x", &message)
-        .unwrap_err();
+    let err = endec2.dec(&OTHER_KEY, &message).unwrap_err();
 
     assert_eq!(EndecError::DecryptionFailed, err);
 }
@@ -80,17 +89,13 @@ fn incorrect_nonce() {
 
     let plaintext = b"secret";
 
-    let mut message = endec1
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
-        .unwrap();
+    let mut message = endec1.enc::<32>(&KEY, plaintext).unwrap();
 
     message.nonce[0] += 1;
 
     let mut endec2 = Endec::new(0);
 
-    let err = endec2
-        .dec(b"0123456789012345678901234567890x", &message)
-        .unwrap_err();
+    let err = endec2.dec(&OTHER_KEY, &message).unwrap_err();
 
     assert_eq!(EndecError::DecryptionFailed, err);
 }
@@ -101,15 +106,1087 @@ fn nonce_varies() {
 
     let plaintext = b"secret";
 
-    let nonce1 = endec1
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
+    let nonce1 = endec1.enc::<32>(&KEY, plaintext).unwrap().nonce;
+
+    let nonce2 = endec1.enc::<32>(&KEY, plaintext).unwrap().nonce;
+
+    assert_ne!(nonce1, nonce2);
+}
+
+#[test]
+fn synthetic_nonce_is_deterministic() {
+    let key = &KEY;
+
+    let nonce1 = Endec::new(3)
+        .synthetic(key, b"secret")
+        .enc::<32>(key, b"secret")
         .unwrap()
         .nonce;
+    let nonce2 = Endec::new(3)
+        .synthetic(key, b"secret")
+        .enc::<32>(key, b"secret")
+        .unwrap()
+        .nonce;
+    assert_eq!(nonce1, nonce2);
 
-    let nonce2 = endec1
-        .enc::<32>(b"01234567890123456789012345678901", plaintext)
+    let nonce3 = Endec::new(4)
+        .synthetic(key, b"secret")
+        .enc::<32>(key, b"secret")
+        .unwrap()
+        .nonce;
+    let nonce4 = Endec::new(3)
+        .synthetic(key, b"secreT")
+        .enc::<32>(key, b"secreT")
+        .unwrap()
+        .nonce;
+    let nonce5 = Endec::new(3)
+        .bind(b"name")
+        .synthetic(key, b"secret")
+        .enc::<32>(key, b"secret")
         .unwrap()
         .nonce;
+    assert_ne!(nonce1, nonce3);
+    assert_ne!(nonce1, nonce4);
+    assert_ne!(nonce1, nonce5);
+}
 
-    assert_ne!(nonce1, nonce2);
+#[test]
+fn roundtrip_bound() {
+    let plaintext = b"secret";
+
+    let message = Endec::new(0)
+        .bind(b"ABCD")
+        .bind(b"abcd_user")
+        .enc::<48>(&KEY, plaintext)
+        .unwrap();
+
+    let replaintext = message.open_bound(&KEY, &[b"ABCD", b"abcd_user"]).unwrap();
+
+    assert_eq!(plaintext, replaintext.as_slice());
+}
+
+#[test]
+fn incorrect_binding() {
+    let message = Endec::new(0)
+        .bind(b"ABCD")
+        .bind(b"abcd_user")
+        .enc::<48>(&KEY, b"secret")
+        .unwrap();
+
+    for bound in [
+        &[b"WXYZ" as &[u8], b"abcd_user"] as &[&[u8]],
+        &[b"ABCD", b"wxyz_user"],
+        &[b"abcd_user", b"ABCD"],
+        &[b"ABC", b"Dabcd_user"],
+        &[b"ABCD"],
+        &[],
+    ] {
+        let err = message.open_bound(&KEY, bound).unwrap_err();
+
+        assert_eq!(EndecError::DecryptionFailed, err);
+    }
+}
+
+#[test]
+fn nonce_starts_from_seed() {
+    let mut endec = Endec::with_nonce(0, [7; 24]);
+
+    let message = endec.enc::<32>(&KEY, b"secret").unwrap();
+
+    let nonce_len = message.algorithm.nonce_len();
+    assert!(message.nonce[..nonce_len].iter().all(|b| *b == 7));
+    assert!(message.nonce[nonce_len..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn supported_algorithms_roundtrip() {
+    for algorithm in Algorithm::ALL.into_iter().filter(|a| a.is_supported()) {
+        let mut endec = Endec::new(0).algorithm(algorithm);
+        let first = endec.enc::<32>(&KEY, b"secret").unwrap();
+        let second = endec.enc::<32>(&KEY, b"secret").unwrap();
+
+        assert_eq!(algorithm, first.algorithm);
+        assert_ne!(first.nonce, second.nonce);
+        assert!(first.nonce[algorithm.nonce_len()..].iter().all(|b| *b == 0));
+        assert_eq!(b"secret", first.open(&KEY).unwrap().as_slice());
+        assert_eq!(b"secret", second.open(&KEY).unwrap().as_slice());
+    }
+}
+
+#[test]
+fn unsupported_algorithms_refused() {
+    let sealed = Endec::new(0).enc::<32>(&KEY, b"secret").unwrap();
+
+    for algorithm in Algorithm::ALL.into_iter().filter(|a| !a.is_supported()) {
+        assert_eq!(
+            Some(EndecError::UnsupportedAlgorithm),
+            Endec::new(0)
+                .algorithm(algorithm)
+                .enc::<32>(&KEY, b"secret")
+                .err()
+        );
+
+        let mut relabelled = sealed.clone();
+        relabelled.algorithm = algorithm;
+        assert_eq!(
+            Some(EndecError::UnsupportedAlgorithm),
+            relabelled.open(&KEY).err()
+        );
+    }
+}
+
+#[test]
+fn algorithm_labels() {
+    for algorithm in Algorithm::ALL {
+        assert_eq!(Some(algorithm), Algorithm::from_label(algorithm.label()));
+    }
+    assert_eq!(None, Algorithm::from_label("Rot13"));
+}
+
+fn unhex<const N: usize>(hex: &str) -> [u8; N] {
+    let mut bytes = [0; N];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn pbkdf2_vectors() {
+    let mut output = [0; 32];
+    pbkdf2(b"password", b"salt", 1, &mut output);
+    assert_eq!(
+        unhex::<32>("120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"),
+        output
+    );
+
+    pbkdf2(b"password", b"salt", 2, &mut output);
+    assert_eq!(
+        unhex::<32>("ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"),
+        output
+    );
+
+    pbkdf2(b"password", b"salt", 4096, &mut output);
+    assert_eq!(
+        unhex::<32>("c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"),
+        output
+    );
+
+    let mut output = [0; 40];
+    pbkdf2(
+        b"passwordPASSWORDpassword",
+        b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+        4096,
+        &mut output,
+    );
+    assert_eq!(
+        unhex::<40>(
+            "348c89dbcbd32b2f32d814b8116e84cf2b17347ebc1800181c4e2a1fb8dd53e1c635518c7dac47e9"
+        ),
+        output
+    );
+}
+
+#[test]
+fn pbkdf2_rfc7914_vector() {
+    let mut output = [0; 64];
+    pbkdf2(b"passwd", b"salt", 1, &mut output);
+    assert_eq!(
+        unhex::<64>(
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        ),
+        output
+    );
+}
+
+#[test]
+fn kdf_uses_salt_and_rounds() {
+    let kdf = Kdf {
+        salt: *b"0123456789abcdef",
+        rounds: 16,
+    };
+
+    let mut expected = [0; 32];
+    pbkdf2(b"ababxy", b"0123456789abcdef", 16, &mut expected);
+    assert!(Key::from_bytes(expected).ct_eq(&kdf.derive(b"ababxy")));
+
+    assert!(!kdf
+        .derive(b"ababxy")
+        .ct_eq(&Kdf { rounds: 17, ..kdf }.derive(b"ababxy")));
+    assert!(!kdf.derive(b"ababxy").ct_eq(&Kdf::DEFAULT.derive(b"ababxy")));
+}
+
+#[test]
+fn entry_roundtrip() {
+    let bytes = Entry::encode::<48>(&[
+        (Field::Name, b"ABCD"),
+        (Field::Username, b"abcd_user"),
+        (Field::Password, b"sw0rd*f1sh"),
+    ])
+    .unwrap();
+
+    let entry = Entry::parse(&bytes).unwrap();
+
+    assert_eq!(b"ABCD", entry.name());
+    assert_eq!(b"abcd_user", entry.username());
+    assert_eq!(b"sw0rd*f1sh", entry.password());
+}
+
+#[test]
+fn entry_skips_unknown_fields() {
+    let bytes = [1, 4, b'A', b'B', b'C', b'D', 99, 2, 0, 0, 3, 1, b'x'];
+
+    let entry = Entry::parse(&bytes).unwrap();
+
+    assert_eq!(3, entry.fields().count());
+    assert_eq!(b"ABCD", entry.name());
+    assert_eq!(b"", entry.username());
+    assert_eq!(b"x", entry.password());
+}
+
+#[test]
+fn entry_extras() {
+    let bytes = Entry::encode::<64>(&[
+        (Field::Name, b"ABCD"),
+        (Field::Extra, b"url\0https://example.com"),
+        (Field::Extra, b"no separator"),
+        (Field::Extra, b"pin\x001234"),
+    ])
+    .unwrap();
+
+    let entry = Entry::parse(&bytes).unwrap();
+    let mut extras = entry.extras();
+
+    assert_eq!(
+        Some((&b"url"[..], &b"https://example.com"[..])),
+        extras.next()
+    );
+    assert_eq!(Some((&b"pin"[..], &b"1234"[..])), extras.next());
+    assert_eq!(None, extras.next());
+}
+
+#[test]
+fn entry_malformed() {
+    for bytes in [&[1u8] as &[u8], &[1, 4, b'A'], &[1, 0, 2]] {
+        assert!(matches!(
+            Entry::parse(bytes),
+            Err(EndecError::MalformedEntry)
+        ));
+    }
+}
+
+#[test]
+fn entry_too_long() {
+    assert_eq!(
+        Some(EndecError::InsufficientBufferCapacity),
+        Entry::encode::<8>(&[(Field::Password, b"0123456789")]).err()
+    );
+    assert_eq!(
+        Some(EndecError::InsufficientBufferCapacity),
+        Entry::encode::<512>(&[(Field::Password, &[0; 256])]).err()
+    );
+}
+
+#[test]
+fn field_labels() {
+    for field in Field::ALL {
+        assert_eq!(Some(field), Field::from_label(field.label()));
+    }
+    assert_eq!(None, Field::from_label("url"));
+}
+
+#[test]
+fn padding_hides_length() {
+    let key = &KEY;
+
+    let short = Endec::new(0).enc::<64>(key, b"a").unwrap();
+    let long = Endec::new(0).enc::<64>(key, b"abcdefghijklmno").unwrap();
+    let longer = Endec::new(0).enc::<64>(key, b"abcdefghijklmnop").unwrap();
+    assert_eq!(32, short.len);
+    assert_eq!(32, long.len);
+    assert_eq!(48, longer.len);
+
+    let bucketed = Endec::new(0).pad_to(32).enc::<64>(key, b"a").unwrap();
+    assert_eq!(48, bucketed.len);
+    assert_eq!(b"a", bucketed.open(key).unwrap().as_slice());
+}
+
+#[test]
+fn padding_limited_by_capacity() {
+    let key = &KEY;
+
+    let message = Endec::new(0).pad_to(64).enc::<48>(key, b"secret").unwrap();
+    assert_eq!(48, message.len);
+    assert_eq!(b"secret", message.open(key).unwrap().as_slice());
+
+    assert!(Endec::new(0).enc::<32>(key, &[b'x'; 15]).is_ok());
+    assert_eq!(
+        Some(EndecError::InsufficientBufferCapacity),
+        Endec::new(0).enc::<32>(key, &[b'x'; 16]).err()
+    );
+}
+
+#[test]
+fn roundtrip_long_passphrase() {
+    let key = &KEY;
+    let plaintext = [b'x'; 100];
+
+    let message = Endec::new(0).enc::<128>(key, &plaintext).unwrap();
+
+    assert_eq!(plaintext, message.open(key).unwrap().as_slice());
+}
+
+#[test]
+fn truncated_secret() {
+    let key = &KEY;
+
+    let mut message = Endec::new(0).enc::<32>(key, b"secret").unwrap();
+    message.len = 33;
+    assert_eq!(Some(EndecError::DecryptionFailed), message.open(key).err());
+
+    message.len = 15;
+    assert_eq!(Some(EndecError::DecryptionFailed), message.open(key).err());
+}
+
+#[test]
+fn sensitive_types_redacted() {
+    extern crate std;
+    use std::format;
+
+    let plaintext = Plaintext::<16>::from_slice(b"secret").unwrap();
+
+    assert_eq!("Key(..)", format!("{:?}", KEY));
+    assert_eq!("Plaintext(..)", format!("{:?}", plaintext));
+}
+
+#[test]
+fn sensitive_types_compare() {
+    let plaintext = Plaintext::<16>::from_slice(b"secret").unwrap();
+
+    assert!(plaintext.ct_eq(b"secret"));
+    assert!(!plaintext.ct_eq(b"secreT"));
+    assert!(!plaintext.ct_eq(b"secret!"));
+
+    assert!(KEY.ct_eq(&KEY));
+    assert!(!KEY.ct_eq(&OTHER_KEY));
+
+    assert_eq!(
+        Some(EndecError::InsufficientBufferCapacity),
+        Plaintext::<4>::from_slice(b"secret").err()
+    );
+}
+
+#[test]
+fn vault_verify() {
+    let vault = Keyring::new(Kdf::DEFAULT, Algorithm::DEFAULT, &KEY, &OTHER_KEY, 0, []).unwrap();
+
+    assert_eq!(Ok(()), vault.verify(&KEY));
+    assert_eq!(Err(EndecError::IncorrectKey), vault.verify(&OTHER_KEY));
+    assert!(vault.open(&KEY).unwrap().ct_eq(&OTHER_KEY));
+}
+
+#[test]
+fn vault_unlock() {
+    let kdf = Kdf {
+        salt: *b"0123456789abcdef",
+        rounds: 16,
+    };
+    let vault = Keyring::new(kdf, Algorithm::DEFAULT, &kdf.derive(b"ababxy"), &KEY, 0, []).unwrap();
+
+    assert!(vault.unlock(b"ababxy").unwrap().ct_eq(&KEY));
+    assert_eq!(
+        Some(EndecError::IncorrectKey),
+        vault.unlock(b"ababxx").err()
+    );
+}
+
+#[test]
+fn vault_rewrap() {
+    let secret = Endec::new(1).enc::<32>(&KEY, b"secret").unwrap();
+    let old = Key::from_bytes([1; 32]);
+    let new = Key::from_bytes([2; 32]);
+
+    let mut vault = Keyring::new(Kdf::DEFAULT, Algorithm::DEFAULT, &old, &KEY, 0, []).unwrap();
+    vault.rewrap(&old, &new).unwrap();
+
+    assert_eq!(Err(EndecError::IncorrectKey), vault.verify(&old));
+    let data_key = vault.open(&new).unwrap();
+    assert_eq!(b"secret", secret.open(&data_key).unwrap().as_slice());
+
+    assert_eq!(
+        Err(EndecError::IncorrectKey),
+        vault.rewrap(&old, &OTHER_KEY)
+    );
+}
+
+#[test]
+fn vault_add_code() {
+    let first = Key::from_bytes([1; 32]);
+    let second = Key::from_bytes([2; 32]);
+    let third = Key::from_bytes([3; 32]);
+
+    let mut vault = Keyring::new(Kdf::DEFAULT, Algorithm::DEFAULT, &first, &KEY, 0, []).unwrap();
+    assert_eq!(
+        Err(EndecError::IncorrectKey),
+        vault.add_code(&second, &third)
+    );
+
+    vault.add_code(&first, &second).unwrap();
+    assert!(vault.open(&first).unwrap().ct_eq(&KEY));
+    assert!(vault.open(&second).unwrap().ct_eq(&KEY));
+
+    // the wrapped keys differ even though the data key is the same
+    let [Some(a), Some(b)] = &vault.slots else {
+        panic!("expected both slots to be filled");
+    };
+    assert_ne!(a.ciphertext, b.ciphertext);
+
+    assert_eq!(Err(EndecError::NoFreeSlot), vault.add_code(&second, &third));
+}
+
+#[test]
+fn secret_wire_roundtrip() {
+    let secret = Endec::new(7).enc::<64>(&KEY, b"secret").unwrap();
+
+    let mut bytes = [0; 128];
+    let len = secret.encode(&mut bytes).unwrap();
+    assert_eq!(secret.encoded_len(), len);
+    assert_eq!([1, secret.algorithm as u8, 7], bytes[..3]);
+
+    let decoded = Secret::<64>::decode(&bytes[..len]).unwrap();
+    assert_eq!(secret.algorithm, decoded.algorithm);
+    assert_eq!(secret.nonce, decoded.nonce);
+    assert_eq!(b"secret", decoded.open(&KEY).unwrap().as_slice());
+
+    // a smaller capacity works as long as the ciphertext fits
+    let small = Secret::<32>::decode(&bytes[..len]).unwrap();
+    assert_eq!(b"secret", small.open(&KEY).unwrap().as_slice());
+
+    // nonces are stored at the algorithm's own length
+    let mut extended = secret.clone();
+    extended.algorithm = Algorithm::XChaCha20Poly1305;
+    extended.nonce = [9; 24];
+    let len = extended.encode(&mut bytes).unwrap();
+    let longer = Algorithm::XChaCha20Poly1305.nonce_len() - secret.algorithm.nonce_len();
+    assert_eq!(len, secret.encoded_len() + longer);
+    assert_eq!([9; 24], Secret::<64>::decode(&bytes[..len]).unwrap().nonce);
+}
+
+#[test]
+fn secret_wire_rejects_malformed() {
+    let secret = Endec::new(7).enc::<64>(&KEY, b"secret").unwrap();
+    let mut bytes = [0; 128];
+    let len = secret.encode(&mut bytes).unwrap();
+    let encoded = &bytes[..len];
+
+    for prefix in 0..len {
+        assert_eq!(
+            Some(EndecError::Truncated),
+            Secret::<64>::decode(&encoded[..prefix]).err()
+        );
+    }
+    assert_eq!(
+        Some(EndecError::TrailingBytes),
+        Secret::<64>::decode(&bytes[..len + 1]).err()
+    );
+    assert_eq!(
+        Some(EndecError::InvalidLength),
+        Secret::<16>::decode(encoded).err()
+    );
+    assert_eq!(
+        Some(EndecError::InsufficientBufferCapacity),
+        secret.encode(&mut [0; 128][..len - 1]).err()
+    );
+
+    let mut corrupt = [0; 128];
+    corrupt[..len].copy_from_slice(encoded);
+    corrupt[0] = 2;
+    assert_eq!(
+        Some(EndecError::UnsupportedVersion),
+        Secret::<64>::decode(&corrupt[..len]).err()
+    );
+
+    corrupt[..len].copy_from_slice(encoded);
+    corrupt[1] = 0xee;
+    assert_eq!(
+        Some(EndecError::UnsupportedAlgorithm),
+        Secret::<64>::decode(&corrupt[..len]).err()
+    );
+
+    // shorter than a tag, and correspondingly truncated
+    let len_at = 3 + secret.algorithm.nonce_len();
+    corrupt[..len].copy_from_slice(encoded);
+    corrupt[len_at..len_at + 2].copy_from_slice(&8u16.to_le_bytes());
+    assert_eq!(
+        Some(EndecError::InvalidLength),
+        Secret::<64>::decode(&corrupt[..len_at + 2 + 8]).err()
+    );
+}
+
+#[test]
+fn vault_wire_roundtrip() {
+    let kdf = Kdf {
+        salt: *b"0123456789abcdef",
+        rounds: 16,
+    };
+    let mut vault =
+        Keyring::new(kdf, Algorithm::DEFAULT, &kdf.derive(b"ababxy"), &KEY, 0, []).unwrap();
+
+    let mut bytes = [0; 256];
+    let len = vault.encode(&mut bytes).unwrap();
+    assert_eq!(vault.encoded_len(), len);
+    assert_eq!(*b"hwpw", bytes[..4]);
+
+    let decoded = Keyring::decode(&bytes[..len]).unwrap();
+    assert_eq!(kdf, decoded.kdf);
+    assert!(decoded.slots[1].is_none());
+    assert!(decoded.unlock(b"ababxy").unwrap().ct_eq(&KEY));
+
+    vault
+        .add_code(&kdf.derive(b"ababxy"), &kdf.derive(b"xyxyab"))
+        .unwrap();
+    let len = vault.encode(&mut bytes).unwrap();
+    let decoded = Keyring::decode(&bytes[..len]).unwrap();
+    assert!(decoded.unlock(b"xyxyab").unwrap().ct_eq(&KEY));
+}
+
+#[test]
+fn vault_wire_rejects_malformed() {
+    let vault = Keyring::new(Kdf::DEFAULT, Algorithm::DEFAULT, &OTHER_KEY, &KEY, 0, []).unwrap();
+    let mut bytes = [0; 256];
+    let len = vault.encode(&mut bytes).unwrap();
+
+    for prefix in 0..len {
+        assert_eq!(
+            Some(EndecError::Truncated),
+            Keyring::decode(&bytes[..prefix]).err()
+        );
+    }
+    assert_eq!(
+        Some(EndecError::TrailingBytes),
+        Keyring::decode(&bytes[..len + 1]).err()
+    );
+
+    let check = |offset: usize, value: &[u8], error: EndecError| {
+        let mut corrupt = bytes;
+        corrupt[offset..offset + value.len()].copy_from_slice(value);
+        assert_eq!(Some(error), Keyring::decode(&corrupt[..len]).err());
+    };
+    check(0, b"hwpx", EndecError::BadMagic);
+    check(4, &[2], EndecError::UnsupportedVersion);
+    check(5, &[2], EndecError::InvalidKdf);
+    check(22, &0u32.to_le_bytes(), EndecError::InvalidKdf);
+    check(26, &[0], EndecError::InvalidLength);
+    check(26, &[3], EndecError::InvalidLength);
+}
+
+const PLAINTEXTS: [&[u8]; 3] = [b"first", b"second", b"third"];
+
+fn sealed_entries() -> [Secret<32>; 3] {
+    core::array::from_fn(|i| Endec::new(i as u8).enc(&KEY, PLAINTEXTS[i]).unwrap())
+}
+
+#[test]
+fn vault_entries_open_in_order() {
+    let vault = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        sealed_entries(),
+    )
+    .unwrap();
+
+    let data_key = vault.open(&OTHER_KEY).unwrap();
+    let mut opened = vault.entries(&data_key).unwrap();
+    for plaintext in PLAINTEXTS {
+        assert_eq!(plaintext, opened.next().unwrap().unwrap().as_slice());
+    }
+    assert!(opened.next().is_none());
+
+    assert_eq!(Ok(()), vault.check_version(3));
+    assert_eq!(Err(EndecError::Rollback), vault.check_version(4));
+}
+
+#[test]
+fn vault_entries_detect_tampering() {
+    let vault = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        sealed_entries(),
+    )
+    .unwrap();
+    let rejected = |vault: &Vault<32, 3>| vault.entries(&KEY).err();
+
+    let mut reordered = vault.clone();
+    reordered.entries.swap(0, 1);
+    assert_eq!(Some(EndecError::IntegrityCheckFailed), rejected(&reordered));
+
+    let mut replaced = vault.clone();
+    replaced.entries[2] = Endec::new(2).enc(&KEY, b"forged").unwrap();
+    assert_eq!(Some(EndecError::IntegrityCheckFailed), rejected(&replaced));
+
+    // claiming a newer version to get past check_version breaks the MAC
+    let mut bumped = vault.clone();
+    bumped.version = 4;
+    assert_eq!(Some(EndecError::IntegrityCheckFailed), rejected(&bumped));
+
+    let [first, second, _] = sealed_entries();
+    let truncated = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        [first, second],
+    )
+    .unwrap();
+    let deleted = Vault::<32, 2> {
+        mac: vault.mac,
+        ..truncated
+    };
+    assert_eq!(
+        Some(EndecError::IntegrityCheckFailed),
+        deleted.entries(&KEY).err()
+    );
+}
+
+#[test]
+fn vault_wire_keeps_entries() {
+    let vault = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        sealed_entries(),
+    )
+    .unwrap();
+
+    let mut bytes = [0; 512];
+    let len = vault.encode(&mut bytes).unwrap();
+    assert_eq!(vault.encoded_len(), len);
+
+    let decoded = Vault::<32, 3>::decode(&bytes[..len]).unwrap();
+    assert_eq!(3, decoded.version);
+    let data_key = decoded.open(&OTHER_KEY).unwrap();
+    let mut opened = decoded.entries(&data_key).unwrap();
+    for plaintext in PLAINTEXTS {
+        assert_eq!(plaintext, opened.next().unwrap().unwrap().as_slice());
+    }
+
+    assert_eq!(
+        Some(EndecError::InvalidLength),
+        Vault::<32, 2>::decode(&bytes[..len]).err()
+    );
+    assert_eq!(
+        Some(EndecError::Truncated),
+        Vault::<32, 3>::decode(&bytes[..len - 33]).err()
+    );
+
+    // the MAC is only checked when opening
+    let mut corrupt = bytes;
+    corrupt[len - 1] ^= 1;
+    let decoded = Vault::<32, 3>::decode(&corrupt[..len]).unwrap();
+    assert_eq!(
+        Some(EndecError::IntegrityCheckFailed),
+        decoded.entries(&KEY).err()
+    );
+}
+
+#[test]
+fn vault_wire_with_entries_apart() {
+    let vault = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        sealed_entries(),
+    )
+    .unwrap();
+    let mut bytes = [0; 512];
+    let len = vault.encode(&mut bytes).unwrap();
+
+    let mut entries = heapless::Vec::<Secret<32>, 4>::new();
+    let keyring =
+        Keyring::decode_with(&bytes[..len], |entry| assert!(entries.push(entry).is_ok())).unwrap();
+    assert_eq!(3, entries.len());
+    assert_eq!(vault.mac, keyring.mac);
+    assert_eq!(keyring.mac, entries_mac(&KEY, keyring.version, &entries));
+
+    let mut again = [0; 512];
+    assert_eq!(len, keyring.encode_with(&entries, &mut again).unwrap());
+    assert_eq!(bytes, again);
+}
+
+#[test]
+fn vault_image_pads_like_erased_flash() {
+    let vault = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        sealed_entries(),
+    )
+    .unwrap();
+    let mut bytes = [0; 512];
+    let len = vault.encode(&mut bytes).unwrap();
+
+    let mut image = [0; 1024];
+    fill_image(&bytes[..len], &mut image).unwrap();
+    assert_eq!(
+        len as u32,
+        u32::from_le_bytes(image[..4].try_into().unwrap())
+    );
+    assert!(image[4 + len..].iter().all(|b| *b == 0xff));
+    let decoded = Vault::<32, 3>::decode(image_contents(&image).unwrap()).unwrap();
+    assert_eq!(vault.mac, decoded.mac);
+
+    image[1023] = 0;
+    assert_eq!(Err(EndecError::TrailingBytes), image_contents(&image));
+    assert_eq!(Err(EndecError::Truncated), image_contents(&[0xff; 1024]));
+    assert_eq!(
+        Err(EndecError::InsufficientBufferCapacity),
+        fill_image(&bytes[..len], &mut [0; 64])
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn sealed_vault_loads_on_the_device() {
+    use crate::{seal, SealedVault, Sealing, StoredVault};
+
+    let sealing = Sealing {
+        capacity: 32,
+        padding: Endec::DEFAULT_BUCKET,
+    };
+    let entries = PLAINTEXTS
+        .iter()
+        .enumerate()
+        .map(|(i, plaintext)| seal(&KEY, Endec::new(i as u8), plaintext, sealing).unwrap())
+        .collect();
+    let mut vault =
+        SealedVault::new(Kdf::DEFAULT, Algorithm::DEFAULT, &OTHER_KEY, &KEY, 3).unwrap();
+    vault.replace(&KEY, entries).unwrap();
+
+    let stored = StoredVault::<32, 4>::decode(&vault.encode().unwrap()).unwrap();
+    let data_key = stored.vault.open(&OTHER_KEY).unwrap();
+    let opened = stored.entries(&data_key).unwrap();
+    assert_eq!(3, stored.entries.len());
+    for (plaintext, opened) in PLAINTEXTS.iter().zip(opened) {
+        assert_eq!(*plaintext, opened.unwrap().as_slice());
+    }
+
+    let decoded = SealedVault::decode(&vault.encode().unwrap()).unwrap();
+    assert_eq!(vault.keyring.mac, decoded.keyring.mac);
+    assert_eq!(3, decoded.entries.len());
+}
+
+/// Flash held in memory, erased to start with.
+struct MemoryFlash([u8; 1024]);
+
+impl Storage for MemoryFlash {
+    type Error = ();
+
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        let region = self.0.get(offset..offset + bytes.len()).ok_or(())?;
+        bytes.copy_from_slice(region);
+        Ok(())
+    }
+}
+
+#[test]
+fn vault_loads_from_flash() {
+    let vault = Vault::new(
+        Kdf::DEFAULT,
+        Algorithm::DEFAULT,
+        &OTHER_KEY,
+        &KEY,
+        3,
+        sealed_entries(),
+    )
+    .unwrap();
+    let mut bytes = [0; 512];
+    let len = vault.encode(&mut bytes).unwrap();
+
+    let mut flash = MemoryFlash([0xff; 1024]);
+    let mut buffer = [0; 1024];
+    assert_eq!(
+        Some(LoadError::Empty),
+        load::<32, 3, _>(&mut flash, &mut buffer).err()
+    );
+
+    fill_image(&bytes[..len], &mut flash.0).unwrap();
+    let loaded = load::<32, 3, _>(&mut flash, &mut buffer).unwrap();
+    assert_eq!(vault.mac, loaded.vault.mac);
+    assert!(loaded.vault.open(&OTHER_KEY).is_ok());
+    let key = KEY;
+    let entries = loaded
+        .entries(&key)
+        .unwrap()
+        .zip(vault.entries(&key).unwrap());
+    for (entry, expected) in entries {
+        assert!(entry.unwrap().ct_eq(&expected.unwrap()));
+    }
+
+    // any number of entries loads, as long as there's room for them
+    let loaded = load::<32, 8, _>(&mut flash, &mut buffer).unwrap();
+    assert_eq!(3, loaded.entries(&KEY).unwrap().count());
+    assert_eq!(
+        Some(LoadError::Invalid(EndecError::TooManyEntries)),
+        load::<32, 2, _>(&mut flash, &mut buffer).err()
+    );
+
+    // the MAC still covers how many there are
+    let mut loaded = load::<32, 3, _>(&mut flash, &mut buffer).unwrap();
+    loaded.entries.pop();
+    assert_eq!(
+        Some(EndecError::IntegrityCheckFailed),
+        loaded.entries(&KEY).err()
+    );
+    assert_eq!(
+        Some(LoadError::Invalid(EndecError::InsufficientBufferCapacity)),
+        load::<32, 3, _>(&mut flash, &mut [0; 512]).err()
+    );
+
+    flash.0[4] ^= 1;
+    assert_eq!(
+        Some(LoadError::Invalid(EndecError::BadMagic)),
+        load::<32, 3, _>(&mut flash, &mut buffer).err()
+    );
+    flash.0[..4].copy_from_slice(&2048u32.to_le_bytes());
+    assert_eq!(
+        Some(LoadError::Invalid(EndecError::Truncated)),
+        load::<32, 3, _>(&mut flash, &mut buffer).err()
+    );
+}
+
+/// NOR flash of four small sectors, which loses power after a set number of writes
+/// and erases, partway through the last.
+struct CutFlash {
+    bytes: [u8; 256],
+    steps_left: Option<usize>,
+    steps: usize,
+    erases: [usize; 4],
+    /// Whether an erase that's cut short gets through all of the sector but its
+    /// nine-byte header, rather than only its first half.
+    cut_erases_keep_header: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct PowerCut;
+
+impl CutFlash {
+    fn new(steps_left: Option<usize>) -> Self {
+        CutFlash {
+            bytes: [0xff; 256],
+            steps_left,
+            steps: 0,
+            erases: [0; 4],
+            cut_erases_keep_header: false,
+        }
+    }
+
+    /// Counts a step, returning whether it's the one the power goes during.
+    fn step(&mut self) -> Result<bool, PowerCut> {
+        self.steps += 1;
+        match &mut self.steps_left {
+            None => Ok(false),
+            Some(0) => Err(PowerCut),
+            Some(left) => {
+                *left -= 1;
+                Ok(*left == 0)
+            }
+        }
+    }
+}
+
+impl Storage for CutFlash {
+    type Error = PowerCut;
+
+    fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), PowerCut> {
+        if self.steps_left == Some(0) {
+            return Err(PowerCut);
+        }
+        bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+        Ok(())
+    }
+}
+
+impl Flash for CutFlash {
+    const SECTOR_SIZE: usize = 64;
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), PowerCut> {
+        let cut = self.step()?;
+        let len = if cut { bytes.len() / 2 } else { bytes.len() };
+        for (old, new) in self.bytes[offset..].iter_mut().zip(&bytes[..len]) {
+            assert_eq!(*new, *old & *new, "setting bits in flash at {offset}");
+            *old = *new;
+        }
+        if cut {
+            return Err(PowerCut);
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, offset: usize) -> Result<(), PowerCut> {
+        let cut = self.step()?;
+        let erased = match (cut, self.cut_erases_keep_header) {
+            (false, _) => 0..64,
+            (true, false) => 0..32,
+            (true, true) => 9..64,
+        };
+        self.bytes[offset + erased.start..offset + erased.end].fill(0xff);
+        if cut {
+            return Err(PowerCut);
+        }
+        self.erases[offset / 64] += 1;
+        Ok(())
+    }
+}
+
+fn value(store: &mut KvStore<&mut CutFlash>, key: u8) -> Option<[u8; 4]> {
+    let mut value = [0; 4];
+    let len = store.get(key, &mut value).unwrap()?;
+    assert_eq!(4, len);
+    Some(value)
+}
+
+/// A setting made once, which only survives by being copied from sector to sector,
+/// then a counter that goes up and a setting which changes now and then, set enough
+/// times to go round the sectors more than once.
+fn changes() -> impl Iterator<Item = (u8, [u8; 4])> {
+    (0..60u32).map(|i| match i % 7 {
+        _ if i == 0 => (0, *b"once"),
+        6 => (2, (i * 1000).to_le_bytes()),
+        _ => (1, i.to_le_bytes()),
+    })
+}
+
+/// Makes `changes` until the power goes, noting the values set and the one being set.
+fn make_changes(
+    flash: &mut CutFlash,
+    set: &mut [Option<[u8; 4]>; 3],
+    setting: &mut Option<(u8, [u8; 4])>,
+) -> Result<(), KvError<PowerCut>> {
+    let mut store = KvStore::open(flash)?;
+    for (key, value) in changes() {
+        *setting = Some((key, value));
+        store.set(key, &value)?;
+        set[key as usize] = Some(value);
+        *setting = None;
+    }
+    Ok(())
+}
+
+#[test]
+fn kv_values_survive_reopening() {
+    let mut flash = CutFlash::new(None);
+    let mut store = KvStore::open(&mut flash).unwrap();
+    assert_eq!(None, value(&mut store, 1));
+    store.set(1, b"abcd").unwrap();
+    store.set(2, b"wxyz").unwrap();
+    store.set(1, b"efgh").unwrap();
+    assert_eq!(Some(*b"efgh"), value(&mut store, 1));
+
+    let mut store = KvStore::open(&mut flash).unwrap();
+    assert_eq!(Some(*b"efgh"), value(&mut store, 1));
+    assert_eq!(Some(*b"wxyz"), value(&mut store, 2));
+    assert_eq!(Err(KvError::TooLong), store.get(1, &mut [0; 2]));
+
+    store.set(3, b"").unwrap();
+    assert_eq!(Ok(Some(0)), store.get(3, &mut []));
+    assert_eq!(Err(KvError::ReservedKey), store.set(0xff, b"abcd"));
+    assert_eq!(Err(KvError::TooLong), store.set(4, &[0; 52]));
+}
+
+#[test]
+fn kv_erases_are_spread_across_sectors() {
+    let mut flash = CutFlash::new(None);
+    let mut store = KvStore::open(&mut flash).unwrap();
+    for i in 0..600u32 {
+        store.set((i % 3) as u8, &i.to_le_bytes()).unwrap();
+    }
+    for key in 0..3 {
+        let last = 597 + key as u32;
+        assert_eq!(Some(last.to_le_bytes()), value(&mut store, key));
+    }
+
+    let erases = flash.erases;
+    let (least, most) = (erases.iter().min().unwrap(), erases.iter().max().unwrap());
+    assert!(*least > 20 && most - least <= 1, "{erases:?}");
+}
+
+#[test]
+fn kv_refuses_values_that_dont_fit() {
+    let mut flash = CutFlash::new(None);
+    let mut store = KvStore::open(&mut flash).unwrap();
+    // a sector holds six of these alongside its header, and the other sectors fill
+    // up before the oldest has to be copied into one
+    for key in 0..18 {
+        store.set(key, &[key; 4]).unwrap();
+    }
+    assert_eq!(Err(KvError::Full), store.set(18, &[18; 4]));
+
+    let mut store = KvStore::open(&mut flash).unwrap();
+    for key in 0..18 {
+        assert_eq!(Some([key; 4]), value(&mut store, key));
+    }
+    assert_eq!(None, value(&mut store, 18));
+}
+
+#[test]
+fn kv_survives_power_cuts_at_every_step() {
+    let mut uncut = CutFlash::new(None);
+    let mut set = [None; 3];
+    make_changes(&mut uncut, &mut set, &mut None).unwrap();
+    assert!(uncut.erases.iter().all(|erases| *erases > 0));
+
+    let cuts = (1..=uncut.steps).flat_map(|cut| [(cut, false), (cut, true)]);
+    for (cut, keep_header) in cuts {
+        let mut flash = CutFlash::new(Some(cut));
+        flash.cut_erases_keep_header = keep_header;
+        let mut set = [None; 3];
+        let mut setting = None;
+        assert_eq!(
+            Err(KvError::Flash(PowerCut)),
+            make_changes(&mut flash, &mut set, &mut setting),
+            "cut at step {cut}"
+        );
+
+        flash.steps_left = None;
+        let mut store = KvStore::open(&mut flash).unwrap();
+        for key in 0..3 {
+            let found = value(&mut store, key);
+            let new = setting
+                .filter(|(setting, _)| *setting == key)
+                .map(|(_, new)| new);
+            assert!(
+                found == set[key as usize] || found.is_some() && found == new,
+                "cut at step {cut}: key {key} is {found:?}, not {:?} or {new:?}",
+                set[key as usize]
+            );
+        }
+
+        // and carries on working
+        for i in 0..20u32 {
+            store.set(1, &i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(Some(19u32.to_le_bytes()), value(&mut store, 1));
+    }
 }
@@ -0,0 +1,195 @@
+use crate::{heapless, Algorithm, Endec, EndecError, Kdf, Key, Plaintext, Secret};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+pub const KEY_SLOTS: usize = 2;
+
+/// A set of entries, sealed under a data key which the vault holds copies of,
+/// wrapped under each code that can unlock it. Codes can be changed or added without
+/// touching the entries.
+///
+/// The entries are covered as a whole by a MAC, which also covers `version`, so that
+/// they can't be reordered, deleted or swapped for those of an older vault with a
+/// lower version.
+#[derive(Clone)]
+pub struct Vault<const N: usize, const COUNT: usize> {
+    pub kdf: Kdf,
+    pub slots: [Option<Secret<64>>; KEY_SLOTS],
+    pub version: u32,
+    pub entries: [Secret<N>; COUNT],
+    pub mac: [u8; 32],
+}
+
+impl<const N: usize, const COUNT: usize> Vault<N, COUNT> {
+    /// Creates a vault with a single slot, which opens with `code_key`. Slots added
+    /// later are wrapped with the same algorithm. The entries must already be sealed
+    /// under `data_key`.
+    pub fn new(
+        kdf: Kdf,
+        algorithm: Algorithm,
+        code_key: &Key,
+        data_key: &Key,
+        version: u32,
+        entries: [Secret<N>; COUNT],
+    ) -> Result<Self, EndecError> {
+        Ok(Vault {
+            kdf,
+            slots: [Some(wrap(algorithm, code_key, data_key)?), None],
+            version,
+            mac: entries_mac(data_key, version, &entries),
+            entries,
+        })
+    }
+
+    /// Unwraps the data key from whichever slot `code_key` opens.
+    pub fn open(&self, code_key: &Key) -> Result<Key, EndecError> {
+        self.find(code_key).map(|(_, data_key)| data_key)
+    }
+
+    pub fn verify(&self, code_key: &Key) -> Result<(), EndecError> {
+        self.find(code_key).map(|_| ())
+    }
+
+    /// Derives the key for `code` and uses it to unwrap the data key.
+    pub fn unlock(&self, code: &[u8]) -> Result<Key, EndecError> {
+        self.open(&self.kdf.derive(code))
+    }
+
+    /// Checks the MAC over the whole set, then decrypts each entry in order.
+    pub fn entries<'a>(
+        &'a self,
+        data_key: &'a Key,
+    ) -> Result<impl Iterator<Item = Result<Plaintext<N>, EndecError>> + 'a, EndecError> {
+        open_entries(data_key, self.version, &self.mac, &self.entries)
+    }
+
+    /// Refuses a vault older than one which has already been seen. The version is
+    /// covered by the MAC, so this is only meaningful once `entries` has succeeded.
+    pub fn check_version(&self, minimum: u32) -> Result<(), EndecError> {
+        if self.version < minimum {
+            Err(EndecError::Rollback)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Changes the code for one slot, leaving any others as they were.
+    pub fn rewrap(&mut self, old_code_key: &Key, new_code_key: &Key) -> Result<(), EndecError> {
+        let (slot, data_key) = self.find(old_code_key)?;
+        let algorithm = self.algorithm(slot);
+        self.slots[slot] = Some(wrap(algorithm, new_code_key, &data_key)?);
+        Ok(())
+    }
+
+    /// Adds a slot for another code, which must be authorised by an existing one.
+    pub fn add_code(&mut self, code_key: &Key, new_code_key: &Key) -> Result<(), EndecError> {
+        let (slot, data_key) = self.find(code_key)?;
+        let algorithm = self.algorithm(slot);
+        let free = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(EndecError::NoFreeSlot)?;
+        *free = Some(wrap(algorithm, new_code_key, &data_key)?);
+        Ok(())
+    }
+
+    fn algorithm(&self, slot: usize) -> Algorithm {
+        self.slots[slot]
+            .as_ref()
+            .map_or(Algorithm::DEFAULT, |secret| secret.algorithm)
+    }
+
+    fn find(&self, code_key: &Key) -> Result<(usize, Key), EndecError> {
+        self.slots
+            .iter()
+            .enumerate()
+            .find_map(|(i, slot)| {
+                let plaintext = slot.as_ref()?.open(code_key).ok()?;
+                let data_key = Key::from_bytes(plaintext.as_slice().try_into().ok()?);
+                Some((i, data_key))
+            })
+            .ok_or(EndecError::IncorrectKey)
+    }
+}
+
+/// A vault as it's loaded from storage, with however many entries it holds up to `MAX`,
+/// so that entries can be added or removed without rebuilding whatever loads it.
+pub struct StoredVault<const N: usize, const MAX: usize> {
+    /// Everything but the entries, which opens and verifies codes as any vault does.
+    pub vault: Vault<N, 0>,
+    pub entries: heapless::Vec<Secret<N>, MAX>,
+}
+
+impl<const N: usize, const MAX: usize> StoredVault<N, MAX> {
+    /// Reads a vault which must take up the whole of `bytes`. This only checks the
+    /// structure; `entries` checks the MAC.
+    pub fn decode(bytes: &[u8]) -> Result<Self, EndecError> {
+        let mut entries = heapless::Vec::new();
+        let mut overflowed = false;
+        let vault = Vault::decode_with(bytes, |entry| {
+            overflowed |= entries.push(entry).is_err();
+        })?;
+        if overflowed {
+            return Err(EndecError::TooManyEntries);
+        }
+        Ok(StoredVault { vault, entries })
+    }
+
+    /// Like `Vault::entries`.
+    pub fn entries<'a>(
+        &'a self,
+        data_key: &'a Key,
+    ) -> Result<impl Iterator<Item = Result<Plaintext<N>, EndecError>> + 'a, EndecError> {
+        open_entries(data_key, self.vault.version, &self.vault.mac, &self.entries)
+    }
+}
+
+/// Checks `mac` over `entries`, then decrypts each of them in order.
+fn open_entries<'a, const N: usize>(
+    data_key: &'a Key,
+    version: u32,
+    mac: &[u8; 32],
+    entries: &'a [Secret<N>],
+) -> Result<impl Iterator<Item = Result<Plaintext<N>, EndecError>> + 'a, EndecError> {
+    let expected = entries_mac(data_key, version, entries);
+    if !bool::from(expected.ct_eq(mac)) {
+        return Err(EndecError::IntegrityCheckFailed);
+    }
+
+    Ok(entries.iter().map(move |secret| secret.open(data_key)))
+}
+
+fn wrap(algorithm: Algorithm, code_key: &Key, data_key: &Key) -> Result<Secret<64>, EndecError> {
+    Endec::new(0)
+        .algorithm(algorithm)
+        .synthetic(code_key, data_key.as_bytes())
+        .enc(code_key, data_key.as_bytes())
+}
+
+/// The MAC stored in a vault. This is exposed for building vaults whose size isn't
+/// known at compile time; it depends only on the sealed contents, not on `N`.
+pub fn entries_mac<const N: usize>(
+    data_key: &Key,
+    version: u32,
+    entries: &[Secret<N>],
+) -> [u8; 32] {
+    // the data key is already used for sealing, so MAC with a key derived from it
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(data_key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"etpwtc vault mac key");
+    let mac_key = mac.finalize().into_bytes();
+
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&mac_key).expect("HMAC accepts keys of any length");
+    mac.update(&version.to_le_bytes());
+    mac.update(&(entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        mac.update(&[entry.context, entry.algorithm as u8]);
+        mac.update(&entry.nonce);
+        mac.update(&(entry.len as u32).to_le_bytes());
+        mac.update(&entry.ciphertext[..entry.len.min(N)]);
+    }
+    mac.finalize().into_bytes().into()
+}
@@ -0,0 +1,321 @@
+//! Byte encodings for storing secrets and vaults outside the firmware image. All
+//! integers are little-endian.
+//!
+//! A secret is encoded as:
+//!
+//! | bytes         | field                                            |
+//! |---------------|--------------------------------------------------|
+//! | 1             | format version, currently 1                      |
+//! | 1             | algorithm ID (see `Algorithm`)                   |
+//! | 1             | context                                          |
+//! | nonce length  | nonce, 12 or 24 bytes depending on the algorithm |
+//! | 2             | sealed length, including padding and tag         |
+//! | sealed length | ciphertext                                       |
+//!
+//! A vault is encoded as:
+//!
+//! | bytes | field                                   |
+//! |-------|-----------------------------------------|
+//! | 4     | magic, `hwpw`                           |
+//! | 1     | format version, currently 1             |
+//! | 1     | KDF ID, 1 for PBKDF2-HMAC-SHA256        |
+//! | 16    | KDF salt                                |
+//! | 4     | KDF rounds                              |
+//! | 1     | number of slots                         |
+//! | ...   | each slot, encoded as a secret          |
+//! | 4     | version                                 |
+//! | 1     | number of entries                       |
+//! | ...   | each entry, encoded as a secret         |
+//! | 32    | MAC over the version and entries        |
+//!
+//! A vault image, for a region of fixed size such as a linker section or a flash
+//! partition, holds an encoded vault as:
+//!
+//! | bytes      | field                                        |
+//! |------------|----------------------------------------------|
+//! | 4          | encoded length of the vault                  |
+//! | length     | the vault                                    |
+//! | ...        | `0xff` to the end of the region, as in erased flash |
+//!
+//! Decoding is strict: anything truncated, oversized, left over or of an unknown
+//! version is rejected rather than guessed at.
+
+use crate::{Algorithm, EndecError, Kdf, Secret, Vault, KEY_SLOTS, MAX_NONCE_LEN, TAG_LEN};
+
+pub const FORMAT_VERSION: u8 = 1;
+pub const VAULT_MAGIC: [u8; 4] = *b"hwpw";
+const KDF_PBKDF2_SHA256: u8 = 1;
+
+impl<const N: usize> Secret<N> {
+    /// Placeholder for decoding into arrays, never produced by a successful decode.
+    const EMPTY: Self = Secret {
+        context: 0,
+        algorithm: Algorithm::ChaCha20Poly1305,
+        nonce: [0; MAX_NONCE_LEN],
+        len: 0,
+        ciphertext: [0; N],
+    };
+
+    pub fn encoded_len(&self) -> usize {
+        3 + self.algorithm.nonce_len() + 2 + self.len
+    }
+
+    /// Writes this secret to the start of `out`, returning the number of bytes used.
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, EndecError> {
+        let mut writer = Writer::new(out);
+        self.write(&mut writer)?;
+        Ok(writer.pos)
+    }
+
+    /// Reads a secret which must take up the whole of `bytes`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, EndecError> {
+        let mut reader = Reader::new(bytes);
+        let secret = Self::read(&mut reader)?;
+        reader.finish()?;
+        Ok(secret)
+    }
+
+    fn write(&self, writer: &mut Writer) -> Result<(), EndecError> {
+        let sealed = self
+            .ciphertext
+            .get(..self.len)
+            .ok_or(EndecError::InvalidLength)?;
+        let len: u16 = self.len.try_into().map_err(|_| EndecError::InvalidLength)?;
+
+        writer.put(&[FORMAT_VERSION, self.algorithm as u8, self.context])?;
+        writer.put(&self.nonce[..self.algorithm.nonce_len()])?;
+        writer.put(&len.to_le_bytes())?;
+        writer.put(sealed)
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, EndecError> {
+        if reader.u8()? != FORMAT_VERSION {
+            return Err(EndecError::UnsupportedVersion);
+        }
+        let algorithm = Algorithm::from_id(reader.u8()?).ok_or(EndecError::UnsupportedAlgorithm)?;
+        let context = reader.u8()?;
+
+        let mut nonce = [0; MAX_NONCE_LEN];
+        let nonce_len = algorithm.nonce_len();
+        nonce[..nonce_len].copy_from_slice(reader.take(nonce_len)?);
+
+        let len = u16::from_le_bytes(reader.array()?) as usize;
+        if len < TAG_LEN || len > N {
+            return Err(EndecError::InvalidLength);
+        }
+        let mut ciphertext = [0; N];
+        ciphertext[..len].copy_from_slice(reader.take(len)?);
+
+        Ok(Secret {
+            context,
+            algorithm,
+            nonce,
+            len,
+            ciphertext,
+        })
+    }
+}
+
+impl<const N: usize, const COUNT: usize> Vault<N, COUNT> {
+    pub fn encoded_len(&self) -> usize {
+        let slots: usize = self.slots.iter().flatten().map(Secret::encoded_len).sum();
+        let entries: usize = self.entries.iter().map(Secret::encoded_len).sum();
+        VAULT_MAGIC.len() + 2 + 16 + 4 + 1 + slots + 4 + 1 + entries + 32
+    }
+
+    /// Writes this vault to the start of `out`, returning the number of bytes used.
+    /// Empty slots aren't stored, so slots are renumbered from zero when decoded.
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, EndecError> {
+        self.write(&self.entries, out)
+    }
+
+    /// Reads a vault which must take up the whole of `bytes` and hold exactly `COUNT`
+    /// entries. This only checks the structure; `entries` checks the MAC.
+    pub fn decode(bytes: &[u8]) -> Result<Self, EndecError> {
+        let mut entries = [Secret::EMPTY; COUNT];
+        let mut next = entries.iter_mut();
+        let keyring = read(bytes, Some(COUNT), |entry| {
+            *next.next().unwrap() = entry;
+        })?;
+
+        Ok(Vault {
+            kdf: keyring.kdf,
+            slots: keyring.slots,
+            version: keyring.version,
+            entries,
+            mac: keyring.mac,
+        })
+    }
+
+    fn write(&self, entries: &[Secret<N>], out: &mut [u8]) -> Result<usize, EndecError> {
+        let mut writer = Writer::new(out);
+        writer.put(&VAULT_MAGIC)?;
+        writer.put(&[FORMAT_VERSION, KDF_PBKDF2_SHA256])?;
+        writer.put(&self.kdf.salt)?;
+        writer.put(&self.kdf.rounds.to_le_bytes())?;
+
+        let count = self.slots.iter().flatten().count();
+        writer.put(&[count as u8])?;
+        for slot in self.slots.iter().flatten() {
+            slot.write(&mut writer)?;
+        }
+
+        let count: u8 = entries
+            .len()
+            .try_into()
+            .map_err(|_| EndecError::InvalidLength)?;
+        writer.put(&self.version.to_le_bytes())?;
+        writer.put(&[count])?;
+        for entry in entries {
+            entry.write(&mut writer)?;
+        }
+        writer.put(&self.mac)?;
+
+        Ok(writer.pos)
+    }
+}
+
+/// Vaults whose number of entries is only known at run time, such as those edited on
+/// the host, keep their entries apart from an empty vault holding everything else.
+impl<const N: usize> Vault<N, 0> {
+    /// Like `encode`, with `entries` in place of the vault's own.
+    pub fn encode_with(&self, entries: &[Secret<N>], out: &mut [u8]) -> Result<usize, EndecError> {
+        self.write(entries, out)
+    }
+
+    /// Like `decode`, but for any number of entries, which are passed to `entry` in
+    /// order. Their MAC is kept, but can only be checked with `entries_mac`.
+    pub fn decode_with(bytes: &[u8], entry: impl FnMut(Secret<N>)) -> Result<Self, EndecError> {
+        read(bytes, None, entry)
+    }
+}
+
+/// Reads a vault, checking its number of entries against `count` if given before
+/// reading any of them.
+fn read<const N: usize>(
+    bytes: &[u8],
+    count: Option<usize>,
+    mut entry: impl FnMut(Secret<N>),
+) -> Result<Vault<N, 0>, EndecError> {
+    let mut reader = Reader::new(bytes);
+    if reader.array()? != VAULT_MAGIC {
+        return Err(EndecError::BadMagic);
+    }
+    if reader.u8()? != FORMAT_VERSION {
+        return Err(EndecError::UnsupportedVersion);
+    }
+    if reader.u8()? != KDF_PBKDF2_SHA256 {
+        return Err(EndecError::InvalidKdf);
+    }
+
+    let salt = reader.array()?;
+    let rounds = u32::from_le_bytes(reader.array()?);
+    if rounds == 0 {
+        return Err(EndecError::InvalidKdf);
+    }
+
+    let slot_count = reader.u8()? as usize;
+    if slot_count == 0 || slot_count > KEY_SLOTS {
+        return Err(EndecError::InvalidLength);
+    }
+    let mut slots = [const { None }; KEY_SLOTS];
+    for slot in slots.iter_mut().take(slot_count) {
+        *slot = Some(Secret::read(&mut reader)?);
+    }
+
+    let version = u32::from_le_bytes(reader.array()?);
+    let entry_count = reader.u8()? as usize;
+    if count.is_some_and(|count| count != entry_count) {
+        return Err(EndecError::InvalidLength);
+    }
+    for _ in 0..entry_count {
+        entry(Secret::read(&mut reader)?);
+    }
+    let mac = reader.array()?;
+
+    reader.finish()?;
+    Ok(Vault {
+        kdf: Kdf { salt, rounds },
+        slots,
+        version,
+        entries: [],
+        mac,
+    })
+}
+
+/// Finds the encoded vault in a vault image, for `Vault::decode`. Erased flash isn't
+/// an image, since its length can't fit.
+pub fn image_contents(image: &[u8]) -> Result<&[u8], EndecError> {
+    let mut reader = Reader::new(image);
+    let len = u32::from_le_bytes(reader.array()?) as usize;
+    let vault = reader.take(len)?;
+    if reader.bytes.iter().any(|b| *b != 0xff) {
+        return Err(EndecError::TrailingBytes);
+    }
+    Ok(vault)
+}
+
+/// Fills the whole of `image` with a vault image holding `vault`, already encoded.
+pub fn fill_image(vault: &[u8], image: &mut [u8]) -> Result<(), EndecError> {
+    let len = u32::try_from(vault.len()).map_err(|_| EndecError::InvalidLength)?;
+    let mut writer = Writer::new(image);
+    writer.put(&len.to_le_bytes())?;
+    writer.put(vault)?;
+    writer.out[writer.pos..].fill(0xff);
+    Ok(())
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], EndecError> {
+        if len > self.bytes.len() {
+            return Err(EndecError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], EndecError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, EndecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn finish(self) -> Result<(), EndecError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(EndecError::TrailingBytes)
+        }
+    }
+}
+
+pub(crate) struct Writer<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(out: &'a mut [u8]) -> Self {
+        Writer { out, pos: 0 }
+    }
+
+    pub(crate) fn put(&mut self, bytes: &[u8]) -> Result<(), EndecError> {
+        self.out
+            .get_mut(self.pos..self.pos + bytes.len())
+            .ok_or(EndecError::InsufficientBufferCapacity)?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Ok(())
+    }
+}
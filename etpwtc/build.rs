@@ -0,0 +1,18 @@
+//! Declares the files the tests read through the macros, which cargo doesn't otherwise
+//! know they depend on. `env("CARGO_PKG_NAME")` needs nothing, since cargo sets it.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    for file in [
+        "key",
+        "vault.toml",
+        "vault.json",
+        "long.toml",
+        "argon2-aes.kdbx",
+        "aeskdf-chacha20.kdbx",
+        "bitwarden.json",
+        "passwords.csv",
+    ] {
+        println!("cargo:rerun-if-changed=testdata/{file}");
+    }
+}
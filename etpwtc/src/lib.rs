@@ -3,5 +3,8 @@
 #[cfg(test)]
 mod tests;
 
-pub use etpwtc_macros::encrypted;
-pub use etpwtc_runtime::{heapless, Endec, Secret};
+pub use etpwtc_macros::{encrypted, encrypted_vault, vault};
+pub use etpwtc_runtime::{
+    heapless, image_contents, load, Algorithm, Endec, EndecError, Entry, Field, Flash, Kdf, Key,
+    KvError, KvStore, LoadError, Plaintext, Secret, Storage, StoredVault, Vault,
+};
@@ -1,14 +1,451 @@
-use etpwtc_macros::encrypted;
-use etpwtc_runtime::{Endec, Secret};
+use etpwtc_macros::{encrypted, encrypted_vault, vault};
+use etpwtc_runtime::{image_contents, Algorithm, Endec, EndecError, Entry, Kdf, Secret, Vault};
 
 const BAKED: Secret<64> = encrypted!(b"ababxy":0, b"Test string - should be decrypted");
 
 #[test]
 fn integration_test() {
     let mut endec = Endec::new(0);
-    let key = Endec::make_key(b"ababxy");
+    let key = Kdf::DEFAULT.derive(b"ababxy");
 
     assert!(
         matches!(endec.dec(&key, &BAKED), Ok(buffer) if buffer.as_slice() == b"Test string - should be decrypted")
     );
 }
+
+const SEVERAL: [Secret<64>; 4] = encrypted!(b"ababxy", [
+    1: b"first",
+    2: b"second",
+    3: b"same",
+    4: b"same",
+]);
+
+#[test]
+fn nonces_unique() {
+    for (i, a) in SEVERAL.iter().enumerate() {
+        for b in SEVERAL[i + 1..].iter() {
+            assert_ne!(a.nonce, b.nonce);
+        }
+    }
+}
+
+const AGAIN: Secret<64> = encrypted!(b"ababxy":4, b"same");
+
+#[test]
+fn secrets_open_themselves() {
+    let key = Kdf::DEFAULT.derive(b"ababxy");
+
+    assert_eq!(b"first", SEVERAL[0].open(&key).unwrap().as_slice());
+    assert_eq!(b"second", SEVERAL[1].open(&key).unwrap().as_slice());
+    assert_eq!(b"same", SEVERAL[2].open(&key).unwrap().as_slice());
+    assert_eq!(b"same", SEVERAL[3].open(&key).unwrap().as_slice());
+}
+
+const BOUND: [Secret<64>; 2] = encrypted!(b"ababxy", [
+    1: b"{32>fFd!" for [b" XYZ", b"xyz-user"],
+    2: b"sw0rd*f1sh" for [b"ABCD", b"abcd_user"],
+]);
+
+#[test]
+fn bound_secrets_need_their_entry() {
+    let key = Kdf::DEFAULT.derive(b"ababxy");
+
+    assert_eq!(
+        b"{32>fFd!",
+        BOUND[0]
+            .open_bound(&key, &[b" XYZ", b"xyz-user"])
+            .unwrap()
+            .as_slice()
+    );
+    assert!(BOUND[0].open_bound(&key, &[b"ABCD", b"abcd_user"]).is_err());
+    assert!(BOUND[1].open(&key).is_err());
+}
+
+const ENTRIES: [Secret<64>; 2] = encrypted!(b"ababxy", [
+    1: { name: b" XYZ", username: b"xyz-user", password: b"{32>fFd!" },
+    2: { name: b"ABCD", password: b"sw0rd*f1sh" },
+]);
+
+#[test]
+fn entries_open_whole() {
+    let key = Kdf::DEFAULT.derive(b"ababxy");
+
+    let plaintext = ENTRIES[0].open(&key).unwrap();
+    let entry = Entry::parse(&plaintext).unwrap();
+    assert_eq!(b" XYZ", entry.name());
+    assert_eq!(b"xyz-user", entry.username());
+    assert_eq!(b"{32>fFd!", entry.password());
+
+    let plaintext = ENTRIES[1].open(&key).unwrap();
+    let entry = Entry::parse(&plaintext).unwrap();
+    assert_eq!(b"ABCD", entry.name());
+    assert_eq!(b"", entry.username());
+    assert_eq!(b"sw0rd*f1sh", entry.password());
+}
+
+const LONG: Secret<128> = encrypted!(
+    b"ababxy":9,
+    b"correct horse battery staple correct horse battery staple correct horse battery staple",
+    capacity = 128,
+    padding = 32,
+);
+
+#[test]
+fn capacity_and_padding_configurable() {
+    let key = Kdf::DEFAULT.derive(b"ababxy");
+
+    assert_eq!(96 + 16, LONG.len);
+    assert_eq!(
+        b"correct horse battery staple correct horse battery staple correct horse battery staple",
+        LONG.open(&key).unwrap().as_slice()
+    );
+
+    // the default bucket is 16 bytes
+    assert_eq!(16 + 16, SEVERAL[0].len);
+    assert_eq!(16 + 16, SEVERAL[1].len);
+}
+
+#[test]
+fn output_reproducible() {
+    assert_eq!(SEVERAL[3].nonce, AGAIN.nonce);
+    assert_eq!(SEVERAL[3].ciphertext, AGAIN.ciphertext);
+}
+
+const VAULT: Vault<64, 0> = vault!(codes = [b"xyxyab"]);
+const SALTED: Vault<64, 0> = vault!(
+    codes = [b"ababxy", b"bbbbbb"],
+    salt = b"0123456789abcdef",
+    rounds = 1000
+);
+
+#[test]
+fn vault_checks_key() {
+    let key = VAULT.unlock(b"xyxyab").unwrap();
+    assert!(VAULT.verify(&Kdf::DEFAULT.derive(b"xyxyab")).is_ok());
+    // the data key is random, not derived from the code
+    assert!(!key.ct_eq(&Kdf::DEFAULT.derive(b"xyxyab")));
+
+    assert_eq!(
+        Some(EndecError::IncorrectKey),
+        VAULT.unlock(b"ababxy").err()
+    );
+    assert!(VAULT.slots[1].is_none());
+}
+
+#[test]
+fn vault_records_kdf() {
+    assert_eq!(*b"0123456789abcdef", SALTED.kdf.salt);
+    assert_eq!(1000, SALTED.kdf.rounds);
+
+    let key = SALTED.unlock(b"ababxy").unwrap();
+    assert!(key.ct_eq(&SALTED.unlock(b"bbbbbb").unwrap()));
+    assert_eq!(
+        Some(EndecError::IncorrectKey),
+        SALTED.verify(&Kdf::DEFAULT.derive(b"ababxy")).err()
+    );
+}
+
+const FILLED: Vault<48, 2> = vault!(
+    codes = [b"xyxyab"],
+    entries = [
+        { name: b"one", username: b"user", password: b"pass" },
+        b"not an entry",
+    ],
+    version = 7,
+    capacity = 48,
+);
+
+static FILLED_IMAGE: &[u8] = vault!(
+    codes = [b"xyxyab"],
+    entries = [
+        { name: b"one", username: b"user", password: b"pass" },
+        b"not an entry",
+    ],
+    version = 7,
+    capacity = 48,
+    image = 1024,
+);
+
+#[test]
+fn vault_image_decodes() {
+    assert_eq!(1024, FILLED_IMAGE.len());
+    let vault = Vault::<48, 2>::decode(image_contents(FILLED_IMAGE).unwrap()).unwrap();
+    assert_eq!(7, vault.version);
+    assert_same_entries(&FILLED, &vault);
+}
+
+/// Checks that two vaults which open with `xyxyab` hold the same entries, which their
+/// MACs can't show since each has a data key of its own.
+fn assert_same_entries<const N: usize, const COUNT: usize>(
+    a: &Vault<N, COUNT>,
+    b: &Vault<N, COUNT>,
+) {
+    let (a_key, b_key) = (a.unlock(b"xyxyab").unwrap(), b.unlock(b"xyxyab").unwrap());
+    let (a, b) = (a.entries(&a_key).unwrap(), b.entries(&b_key).unwrap());
+    for (a, b) in a.zip(b) {
+        assert_eq!(a.unwrap().as_slice(), b.unwrap().as_slice());
+    }
+}
+
+#[test]
+fn vault_holds_entries() {
+    let key = FILLED.unlock(b"xyxyab").unwrap();
+    let mut entries = FILLED.entries(&key).unwrap();
+
+    let first = entries.next().unwrap().unwrap();
+    assert_eq!(b"pass", Entry::parse(&first).unwrap().password());
+    assert_eq!(b"not an entry", entries.next().unwrap().unwrap().as_slice());
+    assert!(entries.next().is_none());
+
+    assert_eq!(0, FILLED.entries[0].context);
+    assert_eq!(1, FILLED.entries[1].context);
+    assert_eq!(7, FILLED.version);
+
+    // every build generates a new data key, even for the same entries
+    let image = Vault::<48, 2>::decode(image_contents(FILLED_IMAGE).unwrap()).unwrap();
+    assert!(!key.ct_eq(&image.unlock(b"xyxyab").unwrap()));
+}
+
+const ALGORITHMS: [Secret<64>; 2] = encrypted!(b"ababxy", [
+    1: b"extended nonce",
+    2: b"misuse resistant",
+], algorithm = XChaCha20Poly1305);
+const SIV: Secret<64> = encrypted!(b"ababxy":3, b"misuse resistant", algorithm = Aes256GcmSiv);
+
+#[test]
+fn algorithm_recorded() {
+    let key = Kdf::DEFAULT.derive(b"ababxy");
+
+    assert_eq!(Algorithm::XChaCha20Poly1305, ALGORITHMS[0].algorithm);
+    assert_eq!(Algorithm::Aes256GcmSiv, SIV.algorithm);
+
+    // the macro seals with anything, but a build can only open what it enables
+    for (secret, plaintext) in [
+        (&ALGORITHMS[0], &b"extended nonce"[..]),
+        (&SIV, b"misuse resistant"),
+    ] {
+        match secret.open(&key) {
+            Ok(opened) => assert_eq!(plaintext, opened.as_slice()),
+            Err(e) => {
+                assert!(!secret.algorithm.is_supported());
+                assert_eq!(EndecError::UnsupportedAlgorithm, e);
+            }
+        }
+    }
+}
+
+const IMPORTED: Vault<96, 2> =
+    encrypted_vault!("testdata/vault.toml", codes = [b"xyxyab"], capacity = 96);
+const IMPORTED_JSON: Vault<96, 2> =
+    encrypted_vault!("testdata/vault.json", codes = [b"xyxyab"], capacity = 96);
+
+#[test]
+fn vault_imported() {
+    let key = IMPORTED.unlock(b"xyxyab").unwrap();
+    let mut entries = IMPORTED.entries(&key).unwrap();
+
+    let first = entries.next().unwrap().unwrap();
+    let first = Entry::parse(&first).unwrap();
+    assert_eq!(b"ABCD", first.name());
+    assert_eq!(b"abcd_user", first.username());
+    assert_eq!(b"sw0rd*f1sh", first.password());
+    let mut extras = first.extras();
+    assert_eq!(
+        Some((&b"url"[..], &b"https://example.com/login"[..])),
+        extras.next()
+    );
+    assert_eq!(None, extras.next());
+
+    let second = entries.next().unwrap().unwrap();
+    let second = Entry::parse(&second).unwrap();
+    assert_eq!(b"Empty", second.name());
+    assert_eq!(b"", second.password());
+}
+
+#[test]
+fn vault_import_formats_agree() {
+    assert_same_entries(&IMPORTED, &IMPORTED_JSON);
+}
+
+// cargo sets `CARGO_PKG_NAME` for every build, so it stands in for a key from the
+// environment
+const FROM_ENV: Secret<64> = encrypted!(env("CARGO_PKG_NAME"):0, b"from the environment");
+const FROM_FILE: Secret<64> = encrypted!(file("testdata/key"):0, b"from a file");
+const KEYED_VAULT: Vault<64, 0> = vault!(codes = [file("testdata/key")]);
+
+#[test]
+fn key_sources() {
+    let key = Kdf::DEFAULT.derive(b"etpwtc");
+    assert_eq!(
+        b"from the environment",
+        FROM_ENV.open(&key).unwrap().as_slice()
+    );
+
+    // the file's trailing newline isn't part of the key
+    let key = Kdf::DEFAULT.derive(b"ababxy");
+    assert_eq!(b"from a file", FROM_FILE.open(&key).unwrap().as_slice());
+
+    assert!(KEYED_VAULT.unlock(b"ababxy").is_ok());
+}
+
+// both databases hold the same entries, under the password `correct horse`; one uses
+// Argon2 and AES, the other AES-KDF and ChaCha20. `testdata/make-kdbx` writes them, and
+// describes them in full
+const KEEPASS: Vault<96, 2> = encrypted_vault!(
+    "testdata/argon2-aes.kdbx",
+    codes = [b"xyxyab"],
+    group = "Firmware",
+    kdbx_password = b"correct horse",
+    capacity = 96
+);
+const KEEPASS_CHACHA: Vault<96, 2> = encrypted_vault!(
+    "testdata/aeskdf-chacha20.kdbx",
+    codes = [b"xyxyab"],
+    group = "Firmware",
+    kdbx_password = b"correct horse",
+    capacity = 96
+);
+const KEEPASS_NESTED: Vault<64, 1> = encrypted_vault!(
+    "testdata/argon2-aes.kdbx",
+    codes = [b"xyxyab"],
+    group = "Firmware/Nested",
+    kdbx_password = b"correct horse"
+);
+
+#[test]
+fn vault_from_keepass() {
+    let key = KEEPASS.unlock(b"xyxyab").unwrap();
+    let mut entries = KEEPASS.entries(&key).unwrap();
+
+    let first = entries.next().unwrap().unwrap();
+    let first = Entry::parse(&first).unwrap();
+    assert_eq!(b"ABCD", first.name());
+    assert_eq!(b"abcd_user", first.username());
+    // the current password, not the one in the entry's history
+    assert_eq!(b"sw0rd*f1sh", first.password());
+    assert_eq!(
+        Some((&b"url"[..], &b"https://example.com/login"[..])),
+        first.extras().next()
+    );
+
+    let second = entries.next().unwrap().unwrap();
+    let second = Entry::parse(&second).unwrap();
+    assert_eq!(b"Q&A", second.name());
+    assert_eq!(b"", second.username());
+    assert_eq!(b"<&>\"'", second.password());
+    assert_eq!(None, second.extras().next());
+
+    assert_same_entries(&KEEPASS, &KEEPASS_CHACHA);
+}
+
+const KEEPASS_ROOT: Vault<64, 1> = encrypted_vault!(
+    "testdata/argon2-aes.kdbx",
+    codes = [b"xyxyab"],
+    group = "",
+    kdbx_password = b"correct horse"
+);
+
+#[test]
+fn vault_from_keepass_root_group() {
+    let key = KEEPASS_ROOT.unlock(b"xyxyab").unwrap();
+    let entry = KEEPASS_ROOT.entries(&key).unwrap().next().unwrap().unwrap();
+    let entry = Entry::parse(&entry).unwrap();
+    assert_eq!(b"Root", entry.name());
+    assert_eq!(b"nobody", entry.username());
+    assert_eq!(b"at the root", entry.password());
+}
+
+#[test]
+fn vault_from_keepass_subgroup() {
+    let key = KEEPASS_NESTED.unlock(b"xyxyab").unwrap();
+    let entry = KEEPASS_NESTED
+        .entries(&key)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let entry = Entry::parse(&entry).unwrap();
+    assert_eq!(b"Deep", entry.name());
+    assert_eq!(b"deeper", entry.password());
+}
+
+// the folder also holds a note, which isn't a login, and an entry too long for the
+// capacity, which is left out with a warning; `tests/ui/import_warning.rs` checks it,
+// and it's allowed here, since macros' warnings come through the `deprecated` lint
+#[allow(deprecated)]
+const BITWARDEN: Vault<96, 2> = encrypted_vault!(
+    "testdata/bitwarden.json",
+    codes = [b"xyxyab"],
+    folder = "Firmware",
+    short_names = derive,
+    capacity = 96
+);
+// an entry far longer than any capacity the firmware would use is left out too
+#[allow(deprecated)]
+const LONG_ENTRIES: Vault<64, 1> = encrypted_vault!("testdata/long.toml", codes = [b"xyxyab"]);
+const CSV: Vault<96, 2> = encrypted_vault!(
+    "testdata/passwords.csv",
+    codes = [b"xyxyab"],
+    tag = "firmware",
+    short_names = truncate,
+    capacity = 96
+);
+
+#[test]
+fn vault_from_bitwarden() {
+    let key = BITWARDEN.unlock(b"xyxyab").unwrap();
+    let mut entries = BITWARDEN.entries(&key).unwrap();
+
+    let first = entries.next().unwrap().unwrap();
+    let first = Entry::parse(&first).unwrap();
+    assert_eq!(b"ABCD", first.name());
+    assert_eq!(b"abcd_user", first.username());
+    assert_eq!(b"sw0rd*f1sh", first.password());
+    assert_eq!(
+        Some((&b"url"[..], &b"https://example.com/login"[..])),
+        first.extras().next()
+    );
+
+    let second = entries.next().unwrap().unwrap();
+    let second = Entry::parse(&second).unwrap();
+    assert_eq!(b"GE", second.name());
+    assert_eq!(b"gh-pass", second.password());
+    assert_eq!(
+        Some((&b"title"[..], &b"GitHub Enterprise"[..])),
+        second.extras().next()
+    );
+}
+
+#[test]
+fn vault_leaves_out_long_entries() {
+    let key = LONG_ENTRIES.unlock(b"xyxyab").unwrap();
+    let mut entries = LONG_ENTRIES.entries(&key).unwrap();
+
+    let kept = entries.next().unwrap().unwrap();
+    assert_eq!(b"Short", Entry::parse(&kept).unwrap().name());
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn vault_from_csv() {
+    let key = CSV.unlock(b"xyxyab").unwrap();
+    let mut entries = CSV.entries(&key).unwrap();
+
+    let first = entries.next().unwrap().unwrap();
+    let first = Entry::parse(&first).unwrap();
+    assert_eq!(b"ABCD", first.name());
+    assert_eq!(b"sw0rd*f1sh", first.password());
+
+    // named after its host, then truncated
+    let second = entries.next().unwrap().unwrap();
+    let second = Entry::parse(&second).unwrap();
+    assert_eq!(b"gith", second.name());
+    assert_eq!(b"octo", second.username());
+    assert_eq!(b"pa\"ss,word", second.password());
+    let mut extras = second.extras();
+    assert_eq!(
+        Some((&b"url"[..], &b"https://www.github.com/login"[..])),
+        extras.next()
+    );
+    assert_eq!(Some((&b"title"[..], &b"github.com"[..])), extras.next());
+}
@@ -0,0 +1,302 @@
+//! Writes the KeePass databases that `etpwtc`'s tests import, `argon2-aes.kdbx` and
+//! `aeskdf-chacha20.kdbx`, into the directory above. Run it with `cargo run` from here
+//! after changing what they hold.
+//!
+//! Both are KDBX 4.0, under the password `correct horse` with no key file, and hold the
+//! same groups and entries. They differ in everything the importer has to decrypt:
+//!
+//! - `argon2-aes.kdbx` derives its key with Argon2d (1 MiB, 2 iterations, 1 lane),
+//!   encrypts with AES-256-CBC, and gzips its payload.
+//! - `aeskdf-chacha20.kdbx` derives its key with AES-KDF (1000 rounds), encrypts with
+//!   ChaCha20, and leaves its payload uncompressed.
+//!
+//! Protected values use the ChaCha20 inner stream in both. The seeds, salts and IVs
+//! are fixed rather than random, so that running this again writes the same files.
+//!
+//! The tree, with each entry's title, user name and password:
+//!
+//! ```text
+//! Passwords (root)    Root      nobody      at the root
+//! ├── Firmware        ABCD      abcd_user   sw0rd*f1sh, with a URL and an older
+//! │   │                                     password in its history
+//! │   ├──             Q&A       (empty)     <&>"'
+//! │   └── Nested      Deep      deep_user   deeper
+//! └── Recycle Bin     Gone      (none)      deleted
+//! ```
+
+use aes::cipher::{
+    block_padding::Pkcs7, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit, StreamCipher,
+};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use std::{io::Write, path::Path};
+
+const PASSWORD: &[u8] = b"correct horse";
+
+const AES256_CBC: [u8; 16] = uuid(0x31c1f2e6_bf71_4350_be58_05216afc5aff);
+const CHACHA20: [u8; 16] = uuid(0xd6038a2b_8b6f_4cb5_a524_339a31dbb59a);
+const AES_KDF: [u8; 16] = uuid(0xc9d9f39a_628a_4460_bf74_0d08c18a4fea);
+const ARGON2D: [u8; 16] = uuid(0xef636ddf_8c29_444b_91f7_a9a403e30a0c);
+
+const fn uuid(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+/// How a database is locked and stored.
+struct Settings {
+    argon2: bool,
+    aes: bool,
+    gzip: bool,
+}
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let databases = [
+        (
+            "argon2-aes.kdbx",
+            Settings {
+                argon2: true,
+                aes: true,
+                gzip: true,
+            },
+        ),
+        (
+            "aeskdf-chacha20.kdbx",
+            Settings {
+                argon2: false,
+                aes: false,
+                gzip: false,
+            },
+        ),
+    ];
+    for (name, settings) in databases {
+        std::fs::write(dir.join(name), database(&settings)).unwrap();
+    }
+}
+
+/// Stand-in random bytes, different for each `tag`.
+fn seed(tag: u8, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(37).wrapping_add(tag))
+        .collect()
+}
+
+/// A KDBX variant dictionary of `(type, key, value)` items.
+fn variant_dictionary(items: &[(u8, &str, Vec<u8>)]) -> Vec<u8> {
+    let mut out = vec![0x00, 0x01];
+    for (ty, key, value) in items {
+        out.push(*ty);
+        out.extend((key.len() as u32).to_le_bytes());
+        out.extend(key.as_bytes());
+        out.extend((value.len() as u32).to_le_bytes());
+        out.extend(value);
+    }
+    out.push(0);
+    out
+}
+
+/// A header field, as both the outer and inner headers lay them out.
+fn field(out: &mut Vec<u8>, id: u8, data: &[u8]) {
+    out.push(id);
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+}
+
+/// The KDF's parameters, and the key it derives from the password.
+fn kdf(settings: &Settings) -> (Vec<u8>, [u8; 32]) {
+    let composite = Sha256::digest(Sha256::digest(PASSWORD));
+    let salt = seed(2, 32);
+    if settings.argon2 {
+        let params = variant_dictionary(&[
+            (0x42, "$UUID", ARGON2D.to_vec()),
+            (0x42, "S", salt.clone()),
+            (0x04, "P", 1u32.to_le_bytes().to_vec()),
+            (0x05, "M", (1024u64 * 1024).to_le_bytes().to_vec()),
+            (0x05, "I", 2u64.to_le_bytes().to_vec()),
+            (0x04, "V", 0x13u32.to_le_bytes().to_vec()),
+        ]);
+        let argon2 = argon2::Argon2::new(
+            argon2::Algorithm::Argon2d,
+            argon2::Version::V0x13,
+            argon2::Params::new(1024, 2, 1, Some(32)).unwrap(),
+        );
+        let mut key = [0; 32];
+        argon2
+            .hash_password_into(&composite, &salt, &mut key)
+            .unwrap();
+        (params, key)
+    } else {
+        let rounds = 1000u64;
+        let params = variant_dictionary(&[
+            (0x42, "$UUID", AES_KDF.to_vec()),
+            (0x05, "R", rounds.to_le_bytes().to_vec()),
+            (0x42, "S", salt.clone()),
+        ]);
+        let cipher = aes::Aes256::new_from_slice(&salt).unwrap();
+        let mut key = composite;
+        for _ in 0..rounds {
+            for block in key.chunks_mut(16) {
+                cipher.encrypt_block(block.into());
+            }
+        }
+        (params, Sha256::digest(key).into())
+    }
+}
+
+/// The XML document, with protected values encrypted by `protect`.
+fn xml(mut protect: impl FnMut(&str) -> String) -> String {
+    let string =
+        |key: &str, value: &str| format!("<String><Key>{key}</Key><Value>{value}</Value></String>");
+    let mut entry = |uuid: &str, title: &str, username: Option<&str>, password: &str| {
+        let username = match username {
+            Some("") => "<String><Key>UserName</Key><Value/></String>".to_string(),
+            Some(username) => string("UserName", username),
+            None => String::new(),
+        };
+        format!(
+            "<UUID>{uuid}</UUID>{}{username}<String><Key>Password</Key>\
+             <Value Protected=\"True\">{}</Value></String>",
+            string("Title", title),
+            protect(password)
+        )
+    };
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>\
+         <Meta><Generator>fixture</Generator><RecycleBinEnabled>True</RecycleBinEnabled>\
+         </Meta><Root><Group><UUID>AAAAAAAAAAAAAAAAAAAAAA==</UUID><Name>Passwords</Name>",
+    );
+    xml += "<Entry>";
+    xml += &entry(
+        "AQAAAAAAAAAAAAAAAAAAAA==",
+        "Root",
+        Some("nobody"),
+        "at the root",
+    );
+    xml += "</Entry>";
+
+    xml += "<Group><UUID>AgAAAAAAAAAAAAAAAAAAAA==</UUID><Name>Firmware</Name>\
+            <Notes>imported</Notes>";
+    xml += "<Entry>";
+    xml += &entry(
+        "AwAAAAAAAAAAAAAAAAAAAA==",
+        "ABCD",
+        Some("abcd_user"),
+        "sw0rd*f1sh",
+    );
+    xml += &string("URL", "https://example.com/login");
+    xml += "<History><Entry>";
+    xml += &entry(
+        "AwAAAAAAAAAAAAAAAAAAAA==",
+        "ABCD",
+        Some("abcd_user"),
+        "old password",
+    );
+    xml += "</Entry></History></Entry>";
+    xml += "<Entry>";
+    xml += &entry("BAAAAAAAAAAAAAAAAAAAAA==", "Q&amp;A", Some(""), "<&>\"'");
+    xml += "</Entry>";
+
+    xml += "<Group><UUID>BQAAAAAAAAAAAAAAAAAAAA==</UUID><Name>Nested</Name><Entry>";
+    xml += &entry(
+        "BgAAAAAAAAAAAAAAAAAAAA==",
+        "Deep",
+        Some("deep_user"),
+        "deeper",
+    );
+    xml += "</Entry></Group></Group>";
+
+    xml += "<Group><UUID>BwAAAAAAAAAAAAAAAAAAAA==</UUID><Name>Recycle Bin</Name><Entry>";
+    xml += &entry("CAAAAAAAAAAAAAAAAAAAAA==", "Gone", None, "deleted");
+    xml += "</Entry></Group></Group><DeletedObjects/></Root></KeePassFile>\n";
+    xml
+}
+
+fn database(settings: &Settings) -> Vec<u8> {
+    let master_seed = seed(1, 32);
+    let (kdf_params, transformed) = kdf(settings);
+    let master_key = Sha256::new()
+        .chain_update(&master_seed)
+        .chain_update(transformed)
+        .finalize();
+    let hmac_base = Sha512::new()
+        .chain_update(&master_seed)
+        .chain_update(transformed)
+        .chain_update([1])
+        .finalize();
+    let block_mac = |index: u64| {
+        let key = Sha512::new()
+            .chain_update(index.to_le_bytes())
+            .chain_update(hmac_base)
+            .finalize();
+        <Hmac<Sha256> as Mac>::new_from_slice(&key).unwrap()
+    };
+
+    // the inner header picks ChaCha20, keyed from the hash of its key
+    let inner_key = seed(3, 64);
+    let inner_hash = Sha512::digest(&inner_key);
+    let mut stream =
+        chacha20::ChaCha20::new_from_slices(&inner_hash[..32], &inner_hash[32..44]).unwrap();
+    let protect = |value: &str| {
+        let mut bytes = value.as_bytes().to_vec();
+        stream.apply_keystream(&mut bytes);
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    };
+
+    let mut payload = Vec::new();
+    field(&mut payload, 1, &3u32.to_le_bytes());
+    field(&mut payload, 2, &inner_key);
+    field(&mut payload, 0, &[]);
+    payload.extend(xml(protect).as_bytes());
+    if settings.gzip {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&payload).unwrap();
+        payload = gzip.finish().unwrap();
+    }
+
+    let (cipher, iv, encrypted) = if settings.aes {
+        let iv = seed(4, 16);
+        let encrypted = cbc::Encryptor::<aes::Aes256>::new_from_slices(&master_key, &iv)
+            .unwrap()
+            .encrypt_padded_vec_mut::<Pkcs7>(&payload);
+        (AES256_CBC, iv, encrypted)
+    } else {
+        let iv = seed(4, 12);
+        chacha20::ChaCha20::new_from_slices(&master_key, &iv)
+            .unwrap()
+            .apply_keystream(&mut payload);
+        (CHACHA20, iv, payload)
+    };
+
+    let mut header = Vec::new();
+    header.extend(0x9aa2_d903u32.to_le_bytes());
+    header.extend(0xb54b_fb67u32.to_le_bytes());
+    header.extend(0x0004_0000u32.to_le_bytes());
+    field(&mut header, 2, &cipher);
+    field(&mut header, 3, &(settings.gzip as u32).to_le_bytes());
+    field(&mut header, 4, &master_seed);
+    field(&mut header, 7, &iv);
+    field(&mut header, 11, &kdf_params);
+    field(&mut header, 0, b"\r\n\r\n");
+
+    let mut file = header.clone();
+    file.extend(Sha256::digest(&header));
+    let mut mac = block_mac(u64::MAX);
+    mac.update(&header);
+    file.extend(mac.finalize().into_bytes());
+
+    // the payload in HMAC'd blocks, ending with an empty one
+    let mut blocks: Vec<&[u8]> = encrypted.chunks(256).collect();
+    blocks.push(&[]);
+    for (index, block) in blocks.into_iter().enumerate() {
+        let mut mac = block_mac(index as u64);
+        mac.update(&(index as u64).to_le_bytes());
+        mac.update(&(block.len() as i32).to_le_bytes());
+        mac.update(block);
+        file.extend(mac.finalize().into_bytes());
+        file.extend((block.len() as i32).to_le_bytes());
+        file.extend(block);
+    }
+    file
+}
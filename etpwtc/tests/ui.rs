@@ -0,0 +1,9 @@
+// the macros' error messages, checked against `tests/ui/*.stderr`; run with
+// `TRYBUILD=overwrite` to update them after changing a message
+#[test]
+fn ui() {
+    // trybuild builds them as a crate of its own under `target`, so the macros are
+    // pointed back here for `testdata`
+    std::env::set_var("ETPWTC_BASE_DIR", env!("CARGO_MANIFEST_DIR"));
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,7 @@
+use etpwtc::{vault, Vault};
+
+const LETTERS: Vault<64, 0> = vault!(codes = [b"xyxyab", b"abcdef"]);
+
+const LENGTH: Vault<64, 0> = vault!(codes = [b"xyxyab"], code_length = 4);
+
+fn main() {}
@@ -0,0 +1,8 @@
+use etpwtc::{encrypted, Secret};
+
+const DUPLICATE: [Secret<64>; 2] = encrypted!(b"ababxy", [
+    1: b"first",
+    1: b"second",
+]);
+
+fn main() {}
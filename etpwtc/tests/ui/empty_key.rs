@@ -0,0 +1,10 @@
+use etpwtc::{encrypted, vault, Secret, Vault};
+
+const EMPTY_KEY: Secret<64> = encrypted!(b"":0, b"plaintext");
+
+const EMPTY_CODE: Vault<64, 1> = vault!(
+    codes = [b"xyxyab", b""],
+    entries = [{ name: b"ABCD", password: b"sw0rd*f1sh" }]
+);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use etpwtc::vault;
+
+static IMAGE: &[u8] = vault!(
+    codes = [b"xyxyab"],
+    entries = [b"first"],
+    image = 64
+);
+
+fn main() {}
@@ -0,0 +1,15 @@
+// trybuild only checks the output of builds that fail, so the warnings are denied to
+// see them; they come through the `deprecated` lint
+#![deny(deprecated)]
+
+use etpwtc::{encrypted_vault, Algorithm, Kdf, Secret, Vault};
+
+const BITWARDEN: Vault<96, 2> = encrypted_vault!(
+    "testdata/bitwarden.json",
+    codes = [b"xyxyab"],
+    folder = "Firmware",
+    short_names = derive,
+    capacity = 96
+);
+
+fn main() {}
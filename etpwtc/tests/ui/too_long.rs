@@ -0,0 +1,9 @@
+use etpwtc::{encrypted, Secret};
+
+const TOO_LONG: Secret<32> = encrypted!(
+    b"ababxy":0,
+    b"longer than the sixteen bytes left after the tag",
+    capacity = 32
+);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use etpwtc::{vault, Vault};
+
+const TOO_LONG: Vault<32, 1> = vault!(
+    codes = [b"xyxyab"],
+    entries = [{ name: b"ABCD", password: b"longer than the space left for it" }],
+    capacity = 32
+);
+
+fn main() {}
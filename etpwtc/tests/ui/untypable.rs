@@ -0,0 +1,12 @@
+use etpwtc::{encrypted, Algorithm, Secret};
+
+// byte strings aren't typed, so they can hold anything
+const ACCENTED: Secret<64> = encrypted!(b"ababxy":0, b"caf\xc3\xa9");
+
+const CONTROL: [Secret<64>; 2] = encrypted!(b"ababxy", [
+    0: { name: b"ABCD", username: b"abcd_user", password: b"tab\there" },
+    // names are only displayed, so they needn't be typable
+    1: { name: b"\xc3\xa9t\xc3\xa9", password: b"sw0rd*f1sh" },
+]);
+
+fn main() {}
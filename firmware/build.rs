@@ -8,10 +8,12 @@
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
 
+mod secrets_inputs;
+
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -29,6 +31,14 @@ fn main() {
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
 
+    // `secrets.rs` can take codes and entries from the environment or files, which
+    // cargo doesn't otherwise know the firmware depends on, and which `hwsim` has to
+    // find from its own directory too
+    secrets_inputs::track(
+        Path::new("src/secrets.rs"),
+        Path::new(env!("CARGO_MANIFEST_DIR")),
+    );
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
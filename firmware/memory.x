@@ -1,6 +1,15 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 16K - 4K
+
+    /* Settings and counters, kept by the runtime's wear-levelled `KvStore` */
+    /* across these four sectors; nothing is linked here                    */
+    SETTINGS : ORIGIN = 0x10000000 + 2048K - 16K - 4K, LENGTH = 16K
+
+    /* The vault, in the last sector of flash and nothing else, so that  */
+    /* `hwpw patch` can replace it in a built image and `hwpw uf2` can   */
+    /* replace it on the device without touching the firmware            */
+    VAULT : ORIGIN = 0x10000000 + 2048K - 4K, LENGTH = 4K
 
     /* Pick one of the two options for RAM layout     */
 
@@ -15,3 +24,12 @@ MEMORY {
     /* SCRATCH_A: ORIGIN = 0x20040000, LENGTH = 4K    */
     /* SCRATCH_B: ORIGIN = 0x20041000, LENGTH = 4K    */
 }
+
+SECTIONS {
+    .vault ORIGIN(VAULT) : {
+        KEEP(*(.vault));
+    } > VAULT
+} INSERT AFTER .rodata;
+
+ASSERT(SIZEOF(.vault) == 0 || SIZEOF(.vault) == LENGTH(VAULT), "
+A vault image must fill the VAULT region; give `vault!` the same `image` size.");
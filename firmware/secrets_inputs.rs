@@ -0,0 +1,47 @@
+//! Tells cargo about the environment variables and files that `src/secrets.rs` has the
+//! macros read, so that whatever builds it is rebuilt when they change. The macros
+//! can't say so themselves without putting what they read into the build. Shared by
+//! the build scripts of the firmware and of `hwsim`, which both build `secrets.rs`.
+//!
+//! This only looks for the macros' syntax in the source, outside of `//` comment
+//! lines, rather than parsing it, so it misses paths and variable names which aren't
+//! string literals right where the macros take them, such as any built by `concat!` or
+//! another macro. Those have to be declared by hand.
+
+use std::{env, fs, path::Path};
+
+/// Prints the `rerun-if` lines for `secrets`, and has the macros take file paths
+/// relative to `base`, the firmware's directory, whichever crate is being built.
+pub fn track(secrets: &Path, base: &Path) {
+    println!("cargo:rustc-env=ETPWTC_BASE_DIR={}", base.display());
+    println!("cargo:rerun-if-changed={}", secrets.display());
+    let Ok(source) = fs::read_to_string(secrets) else {
+        return;
+    };
+    let source: String = source
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("//"))
+        .flat_map(|line| [line, "\n"])
+        .collect();
+
+    for var in quoted_after(&source, "env(") {
+        println!("cargo:rerun-if-env-changed={var}");
+    }
+    let mut files: Vec<String> = quoted_after(&source, "file(")
+        .chain(quoted_after(&source, "encrypted_vault!("))
+        .map(String::from)
+        .collect();
+    files.extend(quoted_after(&source, "file(env(").filter_map(|var| env::var(var).ok()));
+    for file in files {
+        println!("cargo:rerun-if-changed={}", base.join(file).display());
+    }
+}
+
+/// The contents of the string literals which follow `prefix`, and any whitespace
+/// after it.
+fn quoted_after<'a>(source: &'a str, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+    source.match_indices(prefix).filter_map(move |(at, _)| {
+        let rest = source[at + prefix.len()..].trim_start().strip_prefix('"')?;
+        rest.split('"').next()
+    })
+}
@@ -44,12 +44,8 @@ pub struct LCDPeripherals {
     pub bl_en: peripherals::PIN_20,
 }
 
-pub enum Message {
-    Lock,
-    Wake,
-    Unlock,
-    SetName(&'static [u8; 4]),
-}
+pub use etpwtc_app::LcdMessage as Message;
+use etpwtc_app::VaultProblem;
 
 #[embassy_executor::task]
 pub async fn task(io: LCDPeripherals, msg: &'static Channel<CriticalSectionRawMutex, Message, 2>) {
@@ -102,8 +98,9 @@ pub async fn task(io: LCDPeripherals, msg: &'static Channel<CriticalSectionRawMu
     let mut state = UIState {
         backlight: bl_en,
         snooze_at: None,
-        cred_name: b"INIT",
+        cred_name: *b"INIT",
         unlocked: false,
+        no_vault: None,
     };
 
     loop {
@@ -125,7 +122,7 @@ pub async fn task(io: LCDPeripherals, msg: &'static Channel<CriticalSectionRawMu
             // left switch icons
             credentials_image.draw(&mut driver).unwrap();
             password_image.draw(&mut driver).unwrap();
-        } else {
+        } else if state.no_vault.is_none() {
             locked_image.draw(&mut driver).unwrap();
         }
 
@@ -141,12 +138,22 @@ pub async fn task(io: LCDPeripherals, msg: &'static Channel<CriticalSectionRawMu
             // selected password name
             let x = if state.cred_name[0] == b' ' { 70 } else { 78 };
             Text::new(
-                from_utf8(state.cred_name).unwrap(),
+                from_utf8(&state.cred_name).unwrap_or("????"),
                 Point::new(x, 87),
                 text_style,
             )
             .draw(&mut driver)
             .unwrap();
+        } else if let Some(problem) = state.no_vault {
+            // centred, at 16 pixels to a character, with what's wrong underneath
+            Text::new("NO VAULT", Point::new(56, 72), text_style)
+                .draw(&mut driver)
+                .unwrap();
+            let label = problem.label();
+            let x = (240 - 16 * label.len() as i32) / 2;
+            Text::new(label, Point::new(x, 104), text_style)
+                .draw(&mut driver)
+                .unwrap();
         }
     }
 }
@@ -154,8 +161,9 @@ pub async fn task(io: LCDPeripherals, msg: &'static Channel<CriticalSectionRawMu
 struct UIState<'a> {
     backlight: Output<'a, PIN_20>,
     snooze_at: Option<Instant>,
-    cred_name: &'static [u8; 4],
+    cred_name: [u8; 4],
     unlocked: bool,
+    no_vault: Option<VaultProblem>,
 }
 
 impl UIState<'_> {
@@ -171,6 +179,10 @@ impl UIState<'_> {
                 self.snooze_at = None;
                 self.backlight.set_high();
             }
+            Message::NoVault(problem) => {
+                self.no_vault = Some(problem);
+                self.backlight.set_high();
+            }
             Message::Wake => {
                 if self.snooze_at.is_none() {
                     self.backlight.set_high();
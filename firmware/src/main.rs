@@ -4,16 +4,18 @@
 mod debounce;
 mod lcd;
 mod secrets;
+mod storage;
 mod usb;
 
-use core::future::Future;
+use core::future::{pending, Future};
 use debounce::{Debounced, Debouncy};
 use embassy_futures::select::{select4, Either4};
 use embassy_rp::gpio::{Input, Level, Output, Pin};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
-use etpwtc::{heapless::Vec, Endec};
+use etpwtc::{load, KvStore};
+use etpwtc_app::{App, Button, Command, VaultProblem};
 use panic_probe as _;
-use secrets::{CODE_LENGTH, PASS_COUNT};
+use secrets::{CODE_LENGTH, ENTRY_CAPACITY, MAX_ENTRIES};
 
 static LCD: Channel<CriticalSectionRawMutex, lcd::Message, 2> = Channel::new();
 static USB: Channel<CriticalSectionRawMutex, usb::Message, 2> = Channel::new();
@@ -43,131 +45,66 @@ async fn main(spawner: embassy_executor::Spawner) {
     spawner.spawn(lcd::task(lcd, &LCD)).unwrap();
     spawner.spawn(usb::task(io.USB, &USB)).unwrap();
 
-    // passwords, decrypted once the unlock code is available and then stored until reset
-    let mut endec = Endec::new(0);
-    let passwords: [Vec<u8, 64>; PASS_COUNT];
+    let mut flash = storage::flash(io.FLASH);
+    let loaded = {
+        let mut buffer = [0; storage::VAULT_SIZE];
+        let mut region = storage::VaultRegion(&mut flash);
+        load::<ENTRY_CAPACITY, MAX_ENTRIES, _>(&mut region, &mut buffer)
+    };
+    let vault = match loaded {
+        Ok(vault) => vault,
+        // there's nowhere to log to, so the LCD says what's wrong with the vault
+        Err(e) => no_vault(VaultProblem::from(&e)).await,
+    };
 
-    // initial lock screen state: sliding window with index
-    let mut code_window = [0u8; CODE_LENGTH];
-    let mut code_ix = 0;
+    // without the minimum version, an older vault could be flashed over a newer one
+    let mut settings = match KvStore::open(storage::SettingsRegion(&mut flash)) {
+        Ok(settings) => settings,
+        Err(_) => no_vault(VaultProblem::Flash).await,
+    };
+    let minimum_version = match storage::minimum_version(&mut settings) {
+        Ok(version) => version,
+        Err(_) => no_vault(VaultProblem::Flash).await,
+    };
 
-    LCD.send(lcd::Message::SetName(&secrets::PASS_NAMES[0]))
-        .await;
+    // everything from here on is the app's to decide; this only wires it to the hardware
+    let mut app = App::<ENTRY_CAPACITY, MAX_ENTRIES, CODE_LENGTH>::new(vault, minimum_version);
 
     loop {
-        let input = select4(
+        let button = match select4(
             sw_a.debounce(),
             sw_b.debounce(),
             sw_x.debounce(),
             sw_y.debounce(),
         )
-        .await;
-
-        let key = slide_window(input, &mut code_window, &mut code_ix);
-
-        match endec.dec(&key, &secrets::CODE_BUTTONS) {
-            Ok(buffer) if buffer.as_slice() == b"The quick brown fox jumps over the lazy dog." => {
-                let mut associated_data: u8 = 1; // index of calls to encrypted!()
-                passwords = secrets::PASS_WORDS.map(|secret| {
-                    let mut endec = Endec::new(associated_data);
-                    associated_data = associated_data + 1;
-                    endec.dec(&key, &secret).unwrap()
-                });
-
-                code_window = [0; CODE_LENGTH];
-                LCD.send(lcd::Message::Unlock).await;
-                break;
-            }
-            _ => (),
+        .await
+        {
+            Either4::First(_) => Button::A,
+            Either4::Second(_) => Button::B,
+            Either4::Third(_) => Button::X,
+            Either4::Fourth(_) => Button::Y,
         };
-    }
-
-    // extra app state: lock state, current selected password
-    let mut unlocked = true;
-    let mut cred_ix = 0;
-
-    loop {
-        let input = select4(
-            sw_a.debounce(),
-            sw_b.debounce(),
-            sw_x.debounce(),
-            sw_y.debounce(),
-        )
-        .await;
-
-        // activate commands
-        if unlocked {
-            match input {
-                Either4::First(_) => {
-                    unlocked = false;
-                    LCD.send(lcd::Message::Lock).await;
-                }
-                Either4::Second(_) => {
-                    cred_ix = (cred_ix + 1) % secrets::PASS_COUNT;
-                    LCD.send(lcd::Message::SetName(&secrets::PASS_NAMES[cred_ix]))
-                        .await;
-                }
-                Either4::Third(_) => {
-                    let username = secrets::PASS_USERS[cred_ix];
-                    let password = passwords[cred_ix].clone();
 
-                    USB.send(usb::Message::Credentials { username, password })
-                        .await;
-                }
-                Either4::Fourth(_) => {
-                    let password = passwords[cred_ix].clone();
-                    USB.send(usb::Message::Password { password }).await;
-                }
-            }
-
-        // foo
-        } else {
-            let key = slide_window(input, &mut code_window, &mut code_ix);
-
-            match endec.dec(&key, &secrets::CODE_BUTTONS) {
-                Ok(buffer)
-                    if buffer.as_slice() == b"The quick brown fox jumps over the lazy dog." =>
-                {
-                    unlocked = true;
-                    code_window = [0; CODE_LENGTH];
-                    LCD.send(lcd::Message::Unlock).await;
-                }
-                _ => {
-                    LCD.send(lcd::Message::Wake).await;
+        for command in app.press(button) {
+            match command {
+                Command::Lcd(message) => LCD.send(message).await,
+                Command::Usb(message) => USB.send(message).await,
+                // this comes before the vault is unlocked, which it mustn't be if the
+                // version can't be kept
+                Command::KeepVersion(version) => {
+                    if storage::keep_version(&mut settings, version).is_err() {
+                        no_vault(VaultProblem::Flash).await
+                    }
                 }
             }
         }
     }
 }
 
-fn slide_window(
-    input: Either4<(), (), (), ()>,
-    code_window: &mut [u8; CODE_LENGTH],
-    code_ix: &mut usize,
-) -> [u8; 32] {
-    let code_element = match input {
-        Either4::First(_) => b'a',
-        Either4::Second(_) => b'b',
-        Either4::Third(_) => b'x',
-        Either4::Fourth(_) => b'y',
-    };
-
-    code_window[*code_ix] = code_element;
-    *code_ix = (*code_ix + 1) % CODE_LENGTH;
-
-    let sliding_window = code_window[*code_ix..=(CODE_LENGTH - 1)]
-        .iter()
-        .chain(code_window[0..*code_ix].iter());
-
-    let mut sliding_bytes = [0u8; CODE_LENGTH];
-
-    let mut i = 0;
-    for b in sliding_window {
-        sliding_bytes[i] = *b;
-        i += 1;
-    }
-
-    Endec::make_key(&sliding_bytes)
+/// Shows why there's no vault, and then does nothing more until reset.
+async fn no_vault(problem: VaultProblem) -> ! {
+    LCD.send(lcd::Message::NoVault(problem)).await;
+    pending().await
 }
 
 impl<'a, T: Pin> Debouncy for Input<'a, T> {
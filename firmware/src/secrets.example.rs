@@ -1,13 +1,35 @@
-use etpwtc::{encrypted, Secret};
+use etpwtc::vault;
 
+// the firmware loads a vault with these sizes from the VAULT region of flash, and
+// shows "no vault" until there is one; `hwsim`, which runs the app on the host, is
+// built with them too. A vault can hold any number of entries up to MAX_ENTRIES,
+// which is about as many as fit in the region at this capacity
 pub const CODE_LENGTH: usize = 6;
-pub const CODE_BUTTONS: Secret<64> =
-    encrypted!(b"ababxy", b"The quick brown fox jumps over the lazy dog.");
+pub const MAX_ENTRIES: usize = 32;
+pub const ENTRY_CAPACITY: usize = 96;
 
-pub const PASS_COUNT: usize = 2;
-pub const PASS_NAMES: [[u8; 4]; PASS_COUNT] = [*b" XYZ", *b"ABCD"];
-pub const PASS_USERS: [&'static [u8]; PASS_COUNT] = [b"xyz-user", b"abcd_user"];
-pub const PASS_WORDS: [Secret<64>; PASS_COUNT] = [
-    encrypted!(b"ababxy", b"{32>fFd!"),
-    encrypted!(b"ababxy", b"sw0rd*f1sh"),
-];
+// the entries are sealed under a random key, generated afresh for every build, which
+// each unlock code wraps. Codes can be kept out of this file with `env("VAR")` or
+// `file("PATH")` in place of the byte string. Alternatively, this whole file can be
+// generated from a vault file with `hwpw rust`.
+//
+// This vault is only flashed along with the firmware, to start with; make it `&[]` to
+// build a firmware without one. Either way, `hwpw uf2` can replace it on the device
+// and `hwpw patch` in a built firmware, as long as there are no more than MAX_ENTRIES
+// entries and their capacity stays the same. The image has to be the size of the
+// VAULT region.
+//
+// Once the device has opened a vault, it remembers its version and refuses older
+// ones, so give a changed vault a higher `version = ...` than 1, as `hwpw` does
+#[used]
+pub static VAULT_IMAGE: &[u8] = vault!(
+    codes = [b"ababxy"],
+    entries = [
+        { name: b" XYZ", username: b"xyz-user", password: b"{32>fFd!" },
+        { name: b"ABCD", username: b"abcd_user", password: b"sw0rd*f1sh" },
+    ],
+    salt = b"hwpw-example-kdf",
+    capacity = 96,
+    padding = 32,
+    image = 4096,
+);
@@ -0,0 +1,85 @@
+//! The regions of flash set aside in `memory.x`: VAULT, which the vault is loaded from
+//! so that it can be replaced without rebuilding the firmware, and SETTINGS, which
+//! holds what the device has to remember across resets in a `KvStore`. Both borrow
+//! the one flash driver in turn.
+
+use embassy_rp::{
+    flash::{self, Blocking, Error, ERASE_SIZE},
+    peripherals::FLASH,
+};
+use etpwtc::{KvError, KvStore, Storage};
+
+/// The Pico's flash, all of which the driver has to know about.
+const FLASH_SIZE: usize = 2048 * 1024;
+/// The VAULT region, as an offset into flash rather than an address.
+const VAULT_OFFSET: usize = FLASH_SIZE - VAULT_SIZE;
+pub const VAULT_SIZE: usize = 4096;
+/// The SETTINGS region, just below VAULT.
+const SETTINGS_OFFSET: usize = VAULT_OFFSET - SETTINGS_SIZE;
+const SETTINGS_SIZE: usize = 16 * 1024;
+
+/// The key the lowest vault version the app will open is kept under.
+const MINIMUM_VERSION: u8 = 1;
+
+pub type Flash<'d> = flash::Flash<'d, FLASH, Blocking, FLASH_SIZE>;
+
+pub fn flash<'d>(flash: FLASH) -> Flash<'d> {
+    Flash::new_blocking(flash)
+}
+
+pub struct VaultRegion<'a, 'd>(pub &'a mut Flash<'d>);
+
+impl Storage for VaultRegion<'_, '_> {
+    type Error = Error;
+
+    fn size(&self) -> usize {
+        VAULT_SIZE
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error> {
+        self.0.blocking_read((VAULT_OFFSET + offset) as u32, bytes)
+    }
+}
+
+pub struct SettingsRegion<'a, 'd>(pub &'a mut Flash<'d>);
+
+impl Storage for SettingsRegion<'_, '_> {
+    type Error = Error;
+
+    fn size(&self) -> usize {
+        SETTINGS_SIZE
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error> {
+        self.0
+            .blocking_read((SETTINGS_OFFSET + offset) as u32, bytes)
+    }
+}
+
+impl etpwtc::Flash for SettingsRegion<'_, '_> {
+    const SECTOR_SIZE: usize = ERASE_SIZE;
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.0
+            .blocking_write((SETTINGS_OFFSET + offset) as u32, bytes)
+    }
+
+    fn erase(&mut self, offset: usize) -> Result<(), Error> {
+        let from = (SETTINGS_OFFSET + offset) as u32;
+        self.0.blocking_erase(from, from + ERASE_SIZE as u32)
+    }
+}
+
+pub type Settings<'a, 'd> = KvStore<SettingsRegion<'a, 'd>>;
+
+/// The lowest vault version the app will open, which is zero until one has been kept.
+pub fn minimum_version(settings: &mut Settings) -> Result<u32, KvError<Error>> {
+    // shorter values are the same number, as they're little-endian
+    let mut version = [0; 4];
+    settings.get(MINIMUM_VERSION, &mut version)?;
+    Ok(u32::from_le_bytes(version))
+}
+
+pub fn keep_version(settings: &mut Settings, version: u32) -> Result<(), KvError<Error>> {
+    settings.set(MINIMUM_VERSION, &version.to_le_bytes())
+}
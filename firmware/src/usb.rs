@@ -1,4 +1,7 @@
-use crate::debounce::{Debounced, Debouncy};
+use crate::{
+    debounce::{Debounced, Debouncy},
+    secrets::ENTRY_CAPACITY,
+};
 use core::{
     future::Future,
     sync::atomic::{AtomicBool, Ordering},
@@ -14,18 +17,9 @@ use embassy_usb::{
     class::hid::{self, HidWriter},
     Builder, Config, Handler,
 };
-use etpwtc::heapless::Vec;
 use usbd_hid::descriptor::{KeyboardReport, KeyboardUsage::*, SerializedDescriptor};
 
-pub enum Message {
-    Credentials {
-        username: &'static [u8],
-        password: Vec<u8, 64>,
-    },
-    Password {
-        password: Vec<u8, 64>,
-    },
-}
+pub type Message = etpwtc_app::UsbMessage<ENTRY_CAPACITY>;
 
 #[embassy_executor::task]
 pub async fn task(io: USB, msg: &'static Channel<CriticalSectionRawMutex, Message, 2>) {
@@ -77,7 +71,7 @@ pub async fn task(io: USB, msg: &'static Channel<CriticalSectionRawMutex, Messag
         loop {
             match msg.debounce().await {
                 Message::Credentials { username, password } => {
-                    keyboard.send_str(username).await;
+                    keyboard.send_str(username.as_slice()).await;
                     keyboard.send_key(KeyboardTab as u8, false).await;
                     keyboard.send_str(password.as_slice()).await;
                     keyboard.send_key(KeyboardEnter as u8, false).await;
@@ -162,7 +156,7 @@ impl<'a> Keyboard<'a> {
                 b'$' => (Keyboard4Dollar, true),
                 b'%' => (Keyboard5Percent, true),
                 b'&' => (Keyboard7Ampersand, true),
-                b'\'' => (Keyboard3Hash, true),
+                b'\'' => (KeyboardSingleDoubleQuote, false),
                 b'(' => (Keyboard9OpenParens, true),
                 b')' => (Keyboard0CloseParens, true),
                 b'*' => (Keyboard8Asterisk, true),
@@ -173,7 +167,7 @@ impl<'a> Keyboard<'a> {
                 b'/' => (KeyboardSlashQuestion, false),
 
                 b'0' => (Keyboard0CloseParens, false),
-                numeric if numeric >= b'1' && numeric < b'9' => {
+                numeric if numeric >= b'1' && numeric <= b'9' => {
                     ((Keyboard1Exclamation as u8 + numeric - b'1').into(), false)
                 }
 
@@ -0,0 +1,100 @@
+//! Turning a vault file into something the firmware can be built or flashed with.
+//! Neither needs the unlock code, since everything in the vault is already sealed.
+
+use crate::store::Store;
+use etpwtc_runtime::{fill_image, Sealing};
+use std::fmt::Write;
+
+/// A vault image of `size` bytes, which the firmware loads from flash with the same
+/// `load`, with room for `max_entries`.
+pub(crate) fn image(
+    store: &Store,
+    sealing: Sealing,
+    max_entries: usize,
+    size: usize,
+) -> Result<Vec<u8>, String> {
+    check(store, sealing, max_entries)?;
+    let vault = store.encode()?;
+    let mut image = vec![0; size];
+    fill_image(&vault, &mut image).map_err(|_| {
+        format!(
+            "the vault is {} bytes, which doesn't fit an image of {size} bytes with its 4 \
+             byte length",
+            vault.len()
+        )
+    })?;
+    Ok(image)
+}
+
+/// A module for `firmware/src/secrets.rs`, defining the vault image as `vault!` would
+/// along with the sizes the firmware is built for.
+pub(crate) fn rust(
+    store: &Store,
+    sealing: Sealing,
+    size: usize,
+    code_length: usize,
+    max_entries: usize,
+    source: &str,
+) -> Result<String, String> {
+    let image = image(store, sealing, max_entries, size)?;
+    Ok(format!(
+        "\
+// generated by `hwpw rust` from `{source}`; edit the vault with `hwpw` rather than
+// changing this file
+
+pub const CODE_LENGTH: usize = {code_length};
+pub const MAX_ENTRIES: usize = {max_entries};
+pub const ENTRY_CAPACITY: usize = {capacity};
+
+#[used]
+pub static VAULT_IMAGE: &[u8] = {{
+    #[cfg_attr(target_os = \"none\", link_section = \".vault\")]
+    #[used]
+    static IMAGE: [u8; {size}] = {image};
+    &IMAGE
+}};
+",
+        capacity = sealing.capacity,
+        image = bytes(&image, 1),
+    ))
+}
+
+/// The firmware needs at least one entry and no more than it has room for, and each
+/// has to fit its `ENTRY_CAPACITY`.
+fn check(store: &Store, sealing: Sealing, max_entries: usize) -> Result<(), String> {
+    if store.vault.entries.is_empty() {
+        return Err("the vault has no entries; add some with `hwpw add`".into());
+    }
+    if store.vault.entries.len() > max_entries {
+        return Err(format!(
+            "the vault has {} entries, but the firmware only has room for {max_entries}; \
+             raise its `MAX_ENTRIES` and `--max-entries` to match",
+            store.vault.entries.len()
+        ));
+    }
+    if let Some(i) = store
+        .vault
+        .entries
+        .iter()
+        .position(|entry| entry.len > sealing.capacity)
+    {
+        return Err(format!(
+            "entry {} was sealed at a larger capacity than {} bytes; reseal the vault by \
+             editing it with the same `--capacity`",
+            i + 1,
+            sealing.capacity
+        ));
+    }
+    Ok(())
+}
+
+/// An array literal, sixteen bytes to a line.
+fn bytes(bytes: &[u8], indent: usize) -> String {
+    let pad = "    ".repeat(indent);
+    let mut literal = String::from("[\n");
+    for line in bytes.chunks(16) {
+        let line: Vec<_> = line.iter().map(|b| format!("0x{b:02x}")).collect();
+        let _ = writeln!(literal, "{pad}    {},", line.join(", "));
+    }
+    literal + &pad + "]"
+}
@@ -0,0 +1,39 @@
+//! Random passwords, drawn from what the firmware's keyboard layout can type.
+
+use crate::store::random;
+use zeroize::Zeroizing;
+
+const LETTERS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b"!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+pub(crate) fn password(length: usize, symbols: bool) -> Result<Zeroizing<String>, String> {
+    let mut alphabet = [LETTERS, DIGITS].concat();
+    if symbols {
+        alphabet.extend_from_slice(SYMBOLS);
+    }
+
+    // rejection sampling, so that every character is equally likely
+    let limit = 256 - 256 % alphabet.len();
+    let mut password = Zeroizing::new(String::with_capacity(length));
+    while password.len() < length {
+        for byte in random::<32>()? {
+            if (byte as usize) < limit && password.len() < length {
+                password.push(alphabet[byte as usize % alphabet.len()] as char);
+            }
+        }
+    }
+    Ok(password)
+}
+
+/// The firmware types on a US layout, which has keys for printable ASCII and nothing
+/// else, so anything it types has to be made of those.
+pub(crate) fn check_typable(what: &str, value: &str) -> Result<(), String> {
+    match value.chars().find(|c| !(' '..='~').contains(c)) {
+        Some(c) => Err(format!(
+            "{what} has {c:?}, which can't be typed, since the keyboard layout only has \
+             printable ASCII"
+        )),
+        None => Ok(()),
+    }
+}
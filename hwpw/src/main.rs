@@ -0,0 +1,402 @@
+//! `hwpw` creates and edits vault files on the host, with the same sealing code the
+//! firmware opens them with, and turns them into a Rust module to build the firmware
+//! with, a binary image or a UF2 file of its own, or a patch to a firmware that's
+//! already built.
+//!
+//! Codes and passwords are asked for on the terminal, or read a line at a time from
+//! stdin when it isn't one.
+
+mod emit;
+mod generate;
+mod patch;
+mod prompt;
+mod store;
+#[cfg(test)]
+mod tests;
+mod uf2;
+
+use clap::{Args, Parser, Subcommand};
+use etpwtc_runtime::{Algorithm, Endec, Field, Kdf, Sealing};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+use store::{Record, Store};
+use zeroize::Zeroizing;
+
+#[derive(Parser)]
+#[command(version, about = "Manages vault files for the password keyboard")]
+struct Cli {
+    /// The vault file.
+    #[arg(long, short, global = true, default_value = "vault.hwpw")]
+    file: PathBuf,
+
+    /// The space for each entry, which has to match the firmware's `ENTRY_CAPACITY`.
+    #[arg(long, global = true, default_value_t = 96)]
+    capacity: usize,
+
+    /// Entries are padded to a multiple of this many bytes, to hide their lengths.
+    #[arg(long, global = true, default_value_t = Endec::DEFAULT_BUCKET)]
+    padding: usize,
+
+    /// The number of presses in an unlock code, which has to match the firmware's
+    /// `CODE_LENGTH`.
+    #[arg(long, global = true, default_value_t = 6)]
+    code_length: usize,
+
+    /// The most entries the firmware has room for, which has to match its
+    /// `MAX_ENTRIES`.
+    #[arg(long, global = true, default_value_t = 32)]
+    max_entries: usize,
+
+    /// The size of the vault image, which has to match the VAULT region in the
+    /// firmware's `memory.x`.
+    #[arg(long, global = true, default_value_t = 4096)]
+    image_size: usize,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Creates a vault without entries, asking for its unlock code.
+    Create {
+        /// PBKDF2 rounds for turning the unlock code into a key.
+        #[arg(long, default_value_t = Kdf::DEFAULT.rounds)]
+        rounds: u32,
+
+        /// The AEAD to seal with, which the firmware has to be built with.
+        #[arg(long, default_value = Algorithm::DEFAULT.label(), value_parser = algorithm)]
+        algorithm: Algorithm,
+    },
+    /// Lists the entries' names and usernames.
+    List,
+    /// Adds an entry, asking for its password unless one is generated.
+    Add {
+        /// The name shown on the LCD.
+        name: String,
+
+        #[arg(long, short)]
+        username: Option<String>,
+
+        /// An extra field, which isn't typed but is kept with the entry.
+        #[arg(long = "extra", value_name = "LABEL=VALUE", value_parser = extra)]
+        extras: Vec<(String, String)>,
+
+        /// Generates a random password rather than asking for one.
+        #[arg(long)]
+        generate: bool,
+
+        #[command(flatten)]
+        generation: Generation,
+    },
+    /// Changes an entry's fields.
+    Edit {
+        /// The entry's current name, or `#N` for the Nth entry.
+        name: String,
+
+        #[arg(long)]
+        rename: Option<String>,
+
+        #[arg(long, short)]
+        username: Option<String>,
+
+        /// Asks for a new password.
+        #[arg(long, conflicts_with = "generate")]
+        password: bool,
+
+        /// Generates a new random password.
+        #[arg(long)]
+        generate: bool,
+
+        #[command(flatten)]
+        generation: Generation,
+    },
+    /// Removes an entry.
+    Remove {
+        /// The entry's name, or `#N` for the Nth entry.
+        name: String,
+    },
+    /// Changes the unlock code, asking for the old one and then the new one.
+    Code,
+    /// Prints a random password.
+    Generate {
+        #[command(flatten)]
+        generation: Generation,
+    },
+    /// Writes the vault as a Rust module, to use as the firmware's `secrets.rs`.
+    Rust {
+        /// Where to write the module, rather than stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Writes the vault as a binary image.
+    Image {
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Writes the vault image as a UF2 file, which replaces the vault alone when it's
+    /// copied to the RP2040's BOOTSEL drive. The firmware has to have been built for
+    /// as many entries as the vault has.
+    Uf2 {
+        #[arg(long, short)]
+        output: PathBuf,
+
+        /// Where the vault image goes, which is where the VAULT region in `memory.x`
+        /// puts it.
+        #[arg(long, default_value = "0x101ff000", value_parser = address)]
+        address: u32,
+    },
+    /// Replaces the vault in a built firmware, an ELF or UF2 file, which has to have
+    /// been built for as many entries as the vault has.
+    Patch {
+        firmware: PathBuf,
+
+        #[arg(long, short)]
+        output: PathBuf,
+
+        /// Where the vault image is in a UF2 file, which is where the VAULT region in
+        /// `memory.x` puts it; an ELF file names its section.
+        #[arg(long, default_value = "0x101ff000", value_parser = address)]
+        address: u32,
+    },
+}
+
+#[derive(Args)]
+struct Generation {
+    /// The length of generated passwords.
+    #[arg(long, default_value_t = 20)]
+    length: usize,
+
+    /// Generates passwords from only letters and digits.
+    #[arg(long)]
+    no_symbols: bool,
+}
+
+impl Generation {
+    fn password(&self) -> Result<Zeroizing<String>, String> {
+        generate::password(self.length, !self.no_symbols)
+    }
+}
+
+fn algorithm(label: &str) -> Result<Algorithm, String> {
+    Algorithm::from_label(label).ok_or_else(|| {
+        let labels: Vec<_> = Algorithm::ALL.iter().map(|a| a.label()).collect();
+        format!("expected one of {}", labels.join(", "))
+    })
+}
+
+fn address(address: &str) -> Result<u32, String> {
+    let hex = address.strip_prefix("0x").unwrap_or(address);
+    u32::from_str_radix(hex, 16).map_err(|_| "expected a hexadecimal address".into())
+}
+
+fn extra(extra: &str) -> Result<(String, String), String> {
+    extra
+        .split_once('=')
+        .map(|(label, value)| (label.into(), value.into()))
+        .ok_or_else(|| "expected `LABEL=VALUE`".into())
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("hwpw: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let sealing = Sealing {
+        capacity: cli.capacity,
+        padding: cli.padding,
+    };
+    let path = &cli.file;
+
+    match cli.command {
+        Command::Create { rounds, algorithm } => {
+            if path.exists() {
+                return Err(format!("`{}` already exists", path.display()));
+            }
+            if rounds == 0 {
+                return Err("the KDF needs at least one round".into());
+            }
+            let kdf = Kdf {
+                salt: store::random()?,
+                rounds,
+            };
+            let code = prompt::new_secret("Unlock code")?;
+            Store::create(kdf, algorithm, code.as_bytes(), cli.code_length)?.write(path)
+        }
+        Command::List => {
+            let store = Store::read(path)?;
+            let data_key = store.unlock(prompt::secret("Unlock code")?.as_bytes())?;
+            for (i, record) in store.records(&data_key)?.iter().enumerate() {
+                let username = String::from_utf8_lossy(record.get(Field::Username));
+                let line = format!("{:>3}  {:<4}  {username}", i + 1, record.name());
+                println!("{}", line.trim_end());
+            }
+            Ok(())
+        }
+        Command::Add {
+            name,
+            username,
+            extras,
+            generate,
+            generation,
+        } => edit(path, sealing, |records| {
+            let mut record = Record::new(&name);
+            if let Some(username) = &username {
+                generate::check_typable("the username", username)?;
+                record.set(Field::Username, username.as_bytes());
+            }
+            let password = match generate {
+                true => generation.password()?,
+                false => prompt::new_secret("Password")?,
+            };
+            generate::check_typable("the password", &password)?;
+            record.set(Field::Password, password.as_bytes());
+            for (label, value) in &extras {
+                record.add_extra(label, value)?;
+            }
+            records.push(record);
+            Ok(())
+        }),
+        Command::Edit {
+            name,
+            rename,
+            username,
+            password,
+            generate,
+            generation,
+        } => edit(path, sealing, |records| {
+            let index = find(records, &name)?;
+            let record = &mut records[index];
+            if let Some(rename) = &rename {
+                record.set(Field::Name, rename.as_bytes());
+            }
+            if let Some(username) = &username {
+                generate::check_typable("the username", username)?;
+                record.set(Field::Username, username.as_bytes());
+            }
+            let password = match (password, generate) {
+                (true, _) => Some(prompt::new_secret("Password")?),
+                (_, true) => Some(generation.password()?),
+                _ => None,
+            };
+            if let Some(password) = password {
+                generate::check_typable("the password", &password)?;
+                record.set(Field::Password, password.as_bytes());
+            }
+            Ok(())
+        }),
+        Command::Remove { name } => edit(path, sealing, |records| {
+            records.remove(find(records, &name)?);
+            Ok(())
+        }),
+        Command::Code => {
+            let mut store = Store::read(path)?;
+            let old = prompt::secret("Unlock code")?;
+            let new = prompt::new_secret("New unlock code")?;
+            let kdf = store.vault.keyring.kdf;
+            store.change_code(kdf, old.as_bytes(), new.as_bytes(), cli.code_length)?;
+            store.write(path)
+        }
+        Command::Generate { generation } => {
+            println!("{}", *generation.password()?);
+            Ok(())
+        }
+        Command::Rust { output } => {
+            let store = Store::read(path)?;
+            let source = path.display().to_string();
+            let module = emit::rust(
+                &store,
+                sealing,
+                cli.image_size,
+                cli.code_length,
+                cli.max_entries,
+                &source,
+            )?;
+            match output {
+                Some(output) => fs::write(&output, module)
+                    .map_err(|e| format!("couldn't write `{}`: {e}", output.display())),
+                None => {
+                    print!("{module}");
+                    Ok(())
+                }
+            }
+        }
+        Command::Image { output } => {
+            let store = Store::read(path)?;
+            let image = emit::image(&store, sealing, cli.max_entries, cli.image_size)?;
+            fs::write(&output, image)
+                .map_err(|e| format!("couldn't write `{}`: {e}", output.display()))
+        }
+        Command::Uf2 { output, address } => {
+            let store = Store::read(path)?;
+            let image = emit::image(&store, sealing, cli.max_entries, cli.image_size)?;
+            fs::write(&output, uf2::write(address, &image)?)
+                .map_err(|e| format!("couldn't write `{}`: {e}", output.display()))
+        }
+        Command::Patch {
+            firmware,
+            output,
+            address,
+        } => {
+            let store = Store::read(path)?;
+            let mut bytes = fs::read(&firmware)
+                .map_err(|e| format!("couldn't read `{}`: {e}", firmware.display()))?;
+            patch::patch(
+                &mut bytes,
+                &store,
+                sealing,
+                cli.max_entries,
+                address,
+                cli.image_size,
+            )?;
+            fs::write(&output, bytes)
+                .map_err(|e| format!("couldn't write `{}`: {e}", output.display()))
+        }
+    }
+}
+
+/// Unlocks the vault, changes its entries with `change`, then reseals and saves them.
+fn edit(
+    path: &Path,
+    sealing: Sealing,
+    change: impl FnOnce(&mut Vec<Record>) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut store = Store::read(path)?;
+    let data_key = store.unlock(prompt::secret("Unlock code")?.as_bytes())?;
+    let mut records = store.records(&data_key)?;
+    change(&mut records)?;
+    store.replace(&data_key, &records, sealing)?;
+    store.write(path)
+}
+
+/// The index of the one entry called `name`, or of the entry numbered `#N` in `list`.
+fn find(records: &[Record], name: &str) -> Result<usize, String> {
+    if let Some(number) = name.strip_prefix('#').and_then(|n| n.parse::<usize>().ok()) {
+        return match number {
+            1.. if number <= records.len() => Ok(number - 1),
+            _ => Err(format!("there's no entry {name}")),
+        };
+    }
+
+    let mut matching = records
+        .iter()
+        .enumerate()
+        .filter(|(_, record)| record.name() == name);
+    match (matching.next(), matching.next()) {
+        (Some((i, _)), None) => Ok(i),
+        (None, _) => Err(format!("there's no entry called `{name}`")),
+        (Some(_), Some(_)) => Err(format!(
+            "there are several entries called `{name}`; pick one by its number in \
+             `hwpw list`, such as `#1`"
+        )),
+    }
+}
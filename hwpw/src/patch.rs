@@ -0,0 +1,137 @@
+//! Replacing the vault image in a built firmware, so that a prebuilt ELF or UF2 can be
+//! personalized without building it again. Nothing but the image's bytes changes.
+//!
+//! The firmware is built with room for a number of entries and a capacity for each,
+//! which `--max-entries` and `--capacity` have to match since the image doesn't
+//! record them.
+
+use crate::{emit, store::Store, uf2};
+use etpwtc_runtime::{image_contents, Sealing, Vault, MAX_CAPACITY};
+use std::ops::Range;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const SHT_PROGBITS: u32 = 1;
+
+/// Replaces the vault image in `firmware` with `store`'s. A UF2 file doesn't name its
+/// sections, so its image is found at `address` and is `size` bytes long; an ELF
+/// file's is the `.vault` section.
+pub(crate) fn patch(
+    firmware: &mut [u8],
+    store: &Store,
+    sealing: Sealing,
+    max_entries: usize,
+    address: u32,
+    size: usize,
+) -> Result<(), String> {
+    let pieces = if firmware.starts_with(&ELF_MAGIC) {
+        vec![elf_section(firmware, ".vault")?]
+    } else if uf2::is_uf2(firmware) {
+        uf2_region(firmware, address, size)?
+    } else {
+        return Err("the firmware isn't an ELF or UF2 file".into());
+    };
+
+    let old: Vec<u8> = pieces
+        .iter()
+        .flat_map(|piece| &firmware[piece.clone()])
+        .copied()
+        .collect();
+    // checks that the right bytes were found
+    image_contents(&old)
+        .and_then(|vault| Vault::<MAX_CAPACITY, 0>::decode_with(vault, |_| ()))
+        .map_err(|e| format!("the firmware's vault image isn't readable: {e:?}"))?;
+
+    let mut new = emit::image(store, sealing, max_entries, old.len())?.into_iter();
+    for piece in pieces {
+        for (byte, replacement) in firmware[piece].iter_mut().zip(&mut new) {
+            *byte = replacement;
+        }
+    }
+    Ok(())
+}
+
+/// The bytes of the section called `name` in a 32-bit little-endian ELF file, as the
+/// firmware is built.
+pub(crate) fn elf_section(elf: &[u8], name: &str) -> Result<Range<usize>, String> {
+    if elf.get(4..6) != Some(&[1, 1]) {
+        return Err("only 32-bit little-endian ELF files are supported".into());
+    }
+    // every offset comes from the file, so none of them can be trusted not to overflow
+    let malformed = || "the ELF file is malformed".to_string();
+    let add = |a: usize, b: usize| a.checked_add(b).ok_or_else(malformed);
+    let bytes =
+        |offset: usize, len: usize| elf.get(offset..add(offset, len)?).ok_or_else(malformed);
+    let half = |offset: usize| {
+        bytes(offset, 2).map(|b| u16::from_le_bytes(b.try_into().unwrap()) as usize)
+    };
+    let word = |offset: usize| {
+        bytes(offset, 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+    };
+
+    let (headers, header_len, count) = (word(0x20)?, half(0x2e)?, half(0x30)?);
+    let header = |i: usize| {
+        i.checked_mul(header_len)
+            .ok_or_else(malformed)
+            .and_then(|offset| add(headers, offset))
+    };
+    let names = word(add(header(half(0x32)?)?, 16)?)?;
+
+    for i in 0..count {
+        let header = header(i)?;
+        let name_offset = add(names, word(header)?)?;
+        let found = elf
+            .get(name_offset..)
+            .and_then(|rest| rest.split(|b| *b == 0).next())
+            .ok_or_else(malformed)?;
+        if found != name.as_bytes() {
+            continue;
+        }
+
+        if word(add(header, 4)?)? != SHT_PROGBITS as usize {
+            return Err(format!("the ELF file's `{name}` section has no contents"));
+        }
+        let (offset, len) = (word(add(header, 16)?)?, word(add(header, 20)?)?);
+        return match add(offset, len)? {
+            end if end <= elf.len() => Ok(offset..end),
+            _ => Err(malformed()),
+        };
+    }
+    Err(format!(
+        "the ELF file has no `{name}` section; was the firmware built with a vault image?"
+    ))
+}
+
+/// The payload bytes written to flash from `address` up to `size` bytes on, in order,
+/// which have to be there in full.
+fn uf2_region(uf2: &[u8], address: u32, size: usize) -> Result<Vec<Range<usize>>, String> {
+    let start = address as usize;
+    let end = start + size;
+    let mut pieces: Vec<_> = uf2::blocks(uf2)?
+        .into_iter()
+        .filter(|block| block.in_flash() && block.for_rp2040())
+        .filter_map(|block| {
+            let block_start = block.address as usize;
+            let from = start.max(block_start);
+            let to = end.min(block_start + block.payload.len());
+            let payload = block.payload.start;
+            (from < to).then(|| {
+                (
+                    from,
+                    payload + from - block_start..payload + to - block_start,
+                )
+            })
+        })
+        .collect();
+    pieces.sort_by_key(|(from, _)| *from);
+
+    let covered = pieces.iter().try_fold(start, |next, (from, piece)| {
+        (*from == next).then(|| next + piece.len())
+    });
+    if covered != Some(end) {
+        return Err(format!(
+            "the UF2 file doesn't hold the whole vault image at {address:#010x}; was the \
+             firmware built with a vault image of {size} bytes?"
+        ));
+    }
+    Ok(pieces.into_iter().map(|(_, piece)| piece).collect())
+}
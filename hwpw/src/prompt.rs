@@ -0,0 +1,31 @@
+//! Reading codes and passwords: without echo from a terminal, or a line at a time from
+//! whatever else stdin is, so that they can be piped in by scripts.
+
+use std::io::{self, BufRead, IsTerminal};
+use zeroize::Zeroizing;
+
+pub(crate) fn secret(prompt: &str) -> Result<Zeroizing<String>, String> {
+    let read = if io::stdin().is_terminal() {
+        rpassword::prompt_password(format!("{prompt}: "))
+    } else {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line).and_then(|len| {
+            if len == 0 {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            } else {
+                Ok(line.trim_end_matches(['\r', '\n']).to_string())
+            }
+        })
+    };
+    read.map(Zeroizing::new)
+        .map_err(|e| format!("couldn't read the {}: {e}", prompt.to_lowercase()))
+}
+
+/// Reads a secret that's being set, asking twice on a terminal in case of typos.
+pub(crate) fn new_secret(prompt: &str) -> Result<Zeroizing<String>, String> {
+    let first = secret(prompt)?;
+    if io::stdin().is_terminal() && *secret(&format!("{prompt} again"))? != *first {
+        return Err("those didn't match".into());
+    }
+    Ok(first)
+}
@@ -0,0 +1,235 @@
+//! Vault files, in the byte encoding of `etpwtc_runtime::Vault` with however many
+//! entries they hold. Every change reseals all the entries, since each is labelled
+//! with its index, and bumps the version so that the firmware can refuse older vaults.
+
+use etpwtc_runtime::{
+    entries_mac, random_key, seal, Algorithm, Endec, EndecError, Entry, Field, Kdf, Key,
+    SealedVault, Sealing, Secret, MAX_CAPACITY,
+};
+use std::{fs, path::Path};
+use zeroize::Zeroizing;
+
+/// The buttons' letters, which are all an unlock code can be made of.
+const BUTTONS: &[u8] = b"abxy";
+
+pub(crate) struct Store {
+    pub(crate) vault: SealedVault,
+}
+
+impl Store {
+    /// Creates an empty vault which opens with `code`, under a random data key.
+    pub(crate) fn create(
+        kdf: Kdf,
+        algorithm: Algorithm,
+        code: &[u8],
+        code_length: usize,
+    ) -> Result<Store, String> {
+        check_code(code, code_length)?;
+        let data_key = random_key().map_err(|e| format!("couldn't generate the data key: {e}"))?;
+        let vault = SealedVault::new(kdf, algorithm, &kdf.derive(code), &data_key, 1)
+            .map_err(|e| format!("couldn't create the vault: {e:?}"))?;
+        Ok(Store { vault })
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Store, String> {
+        let bytes =
+            fs::read(path).map_err(|e| format!("couldn't read `{}`: {e}", path.display()))?;
+        let vault = SealedVault::decode(&bytes)
+            .map_err(|e| format!("`{}` isn't a readable vault: {e:?}", path.display()))?;
+        Ok(Store { vault })
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, String> {
+        self.vault
+            .encode()
+            .map_err(|e| format!("couldn't encode the vault: {e:?}"))
+    }
+
+    /// Replaces the file at `path` in one step, so that an interrupted write can't
+    /// leave half a vault behind.
+    pub(crate) fn write(&self, path: &Path) -> Result<(), String> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".new");
+        fs::write(&temporary, self.encode()?)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("couldn't write `{}`: {e}", path.display()))
+    }
+
+    pub(crate) fn unlock(&self, code: &[u8]) -> Result<Key, String> {
+        self.vault.keyring.unlock(code).map_err(|e| match e {
+            EndecError::IncorrectKey => "wrong unlock code".to_string(),
+            e => format!("couldn't unlock the vault: {e:?}"),
+        })
+    }
+
+    /// Checks the entries' MAC, then decrypts them all.
+    pub(crate) fn records(&self, data_key: &Key) -> Result<Vec<Record>, String> {
+        let keyring = &self.vault.keyring;
+        if entries_mac(data_key, keyring.version, &self.vault.entries) != keyring.mac {
+            return Err("the vault's entries have been tampered with".into());
+        }
+
+        self.vault
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, secret)| {
+                let plaintext = secret
+                    .open(data_key)
+                    .map_err(|e| format!("couldn't open entry {}: {e:?}", i + 1))?;
+                let entry = Entry::parse(&plaintext)
+                    .map_err(|e| format!("entry {} is malformed: {e:?}", i + 1))?;
+                Ok(Record::from_entry(&entry))
+            })
+            .collect()
+    }
+
+    /// Replaces the entries with `records`, sealed under `data_key`.
+    pub(crate) fn replace(
+        &mut self,
+        data_key: &Key,
+        records: &[Record],
+        sealing: Sealing,
+    ) -> Result<(), String> {
+        if records.len() > u8::MAX as usize + 1 {
+            return Err("vaults are limited to 256 entries".into());
+        }
+        if sealing.capacity > MAX_CAPACITY {
+            return Err(format!("capacity is limited to {MAX_CAPACITY} bytes"));
+        }
+
+        let algorithm = self.vault.algorithm();
+        let entries = records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| seal_record(data_key, i as u8, algorithm, record, sealing))
+            .collect::<Result<_, _>>()?;
+        self.vault.keyring.version += 1;
+        self.vault
+            .replace(data_key, entries)
+            .map_err(|e| format!("couldn't seal the vault: {e:?}"))
+    }
+
+    /// Changes the code for whichever slot `old` opens.
+    pub(crate) fn change_code(
+        &mut self,
+        kdf: Kdf,
+        old: &[u8],
+        new: &[u8],
+        code_length: usize,
+    ) -> Result<(), String> {
+        check_code(new, code_length)?;
+        self.vault
+            .keyring
+            .rewrap(&kdf.derive(old), &kdf.derive(new))
+            .map_err(|e| match e {
+                EndecError::IncorrectKey => "wrong unlock code".to_string(),
+                e => format!("couldn't change the code: {e:?}"),
+            })
+    }
+}
+
+/// Checks that `code` can be entered on a device whose codes are `length` presses.
+fn check_code(code: &[u8], length: usize) -> Result<(), String> {
+    if code.len() != length || !code.iter().all(|b| BUTTONS.contains(b)) {
+        return Err(format!(
+            "the unlock code has to be {length} presses of a, b, x or y"
+        ));
+    }
+    Ok(())
+}
+
+/// Seals a record as the macros would, with a nonce derived from its contents.
+fn seal_record(
+    key: &Key,
+    context: u8,
+    algorithm: Algorithm,
+    record: &Record,
+    sealing: Sealing,
+) -> Result<Secret<MAX_CAPACITY>, String> {
+    let plaintext = record.encode()?;
+    let endec = Endec::new(context).algorithm(algorithm);
+    seal(key, endec, &plaintext, sealing).map_err(|e| match e {
+        EndecError::InsufficientBufferCapacity => format!(
+            "`{}` is {} bytes, too long to seal at a capacity of {} bytes",
+            record.name(),
+            plaintext.len(),
+            sealing.capacity
+        ),
+        e => format!("couldn't seal `{}`: {e:?}", record.name()),
+    })
+}
+
+pub(crate) fn random<const N: usize>() -> Result<[u8; N], String> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|e| format!("couldn't get random bytes: {e}"))?;
+    Ok(bytes)
+}
+
+/// A decrypted entry's fields, in order, including any with tags this version doesn't
+/// know about.
+pub(crate) struct Record {
+    fields: Vec<(u8, Zeroizing<Vec<u8>>)>,
+}
+
+impl Record {
+    pub(crate) fn new(name: &str) -> Record {
+        let mut record = Record { fields: Vec::new() };
+        record.set(Field::Name, name.as_bytes());
+        record
+    }
+
+    fn from_entry(entry: &Entry) -> Record {
+        let fields = entry
+            .fields()
+            .map(|(tag, value)| (tag, Zeroizing::new(value.to_vec())))
+            .collect();
+        Record { fields }
+    }
+
+    pub(crate) fn get(&self, field: Field) -> &[u8] {
+        self.fields
+            .iter()
+            .find(|(tag, _)| *tag == field as u8)
+            .map_or(&[], |(_, value)| value)
+    }
+
+    pub(crate) fn name(&self) -> String {
+        String::from_utf8_lossy(self.get(Field::Name)).into_owned()
+    }
+
+    /// Sets a field with a label of its own, replacing any value it had.
+    pub(crate) fn set(&mut self, field: Field, value: &[u8]) {
+        let value = Zeroizing::new(value.to_vec());
+        match self.fields.iter_mut().find(|(tag, _)| *tag == field as u8) {
+            Some((_, existing)) => *existing = value,
+            None => self.fields.push((field as u8, value)),
+        }
+    }
+
+    pub(crate) fn add_extra(&mut self, label: &str, value: &str) -> Result<(), String> {
+        if label.contains('\0') {
+            return Err("field labels can't contain NUL".into());
+        }
+        let mut extra = Zeroizing::new(label.as_bytes().to_vec());
+        extra.push(0);
+        extra.extend_from_slice(value.as_bytes());
+        self.fields.push((Field::Extra as u8, extra));
+        Ok(())
+    }
+
+    fn encode(&self) -> Result<Zeroizing<Vec<u8>>, String> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        for (tag, value) in &self.fields {
+            let len: u8 = value.len().try_into().map_err(|_| {
+                format!(
+                    "`{}` has a field longer than the 255 bytes a field can hold",
+                    self.name()
+                )
+            })?;
+            bytes.extend_from_slice(&[*tag, len]);
+            bytes.extend_from_slice(value);
+        }
+        Ok(bytes)
+    }
+}
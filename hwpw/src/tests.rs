@@ -0,0 +1,338 @@
+use crate::{
+    emit, find, generate, patch,
+    store::{Record, Store},
+    uf2,
+};
+use etpwtc_runtime::{image_contents, Algorithm, Entry, Field, Kdf, Sealing, StoredVault, Vault};
+
+const KDF: Kdf = Kdf {
+    salt: *b"hwpw-test-salt!!",
+    rounds: 1,
+};
+const SEALING: Sealing = Sealing {
+    capacity: 96,
+    padding: 16,
+};
+/// Where the fixtures, like the firmware, have their vault image.
+const VAULT_ADDRESS: u32 = 0x101f_f000;
+
+fn record(name: &str, username: &str, password: &str) -> Record {
+    let mut record = Record::new(name);
+    record.set(Field::Username, username.as_bytes());
+    record.set(Field::Password, password.as_bytes());
+    record
+}
+
+/// A vault opened by `ababxy`, holding two entries.
+fn filled() -> Store {
+    let mut store = Store::create(KDF, Algorithm::DEFAULT, b"ababxy", 6).unwrap();
+    let data_key = store.unlock(b"ababxy").unwrap();
+    let mut first = record("ABCD", "abcd_user", "sw0rd*f1sh");
+    first.add_extra("url", "https://example.com").unwrap();
+    let records = [first, record("XYZ", "", "{32>fFd!")];
+    store.replace(&data_key, &records, SEALING).unwrap();
+    store
+}
+
+#[test]
+fn entries_survive_a_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault.hwpw");
+    filled().write(&path).unwrap();
+
+    let store = Store::read(&path).unwrap();
+    assert_eq!(2, store.vault.keyring.version);
+    let data_key = store.unlock(b"ababxy").unwrap();
+    let records = store.records(&data_key).unwrap();
+    assert_eq!(2, records.len());
+    assert_eq!("ABCD", records[0].name());
+    assert_eq!(b"abcd_user", records[0].get(Field::Username));
+    assert_eq!(b"sw0rd*f1sh", records[0].get(Field::Password));
+    assert_eq!(b"{32>fFd!", records[1].get(Field::Password));
+
+    assert_eq!(
+        Err("wrong unlock code".into()),
+        store.unlock(b"xyxyab").map(|_| ())
+    );
+}
+
+/// The names of the entries in a vault image, as the firmware would open it, with its
+/// sizes fixed at compile time.
+fn names(image: &[u8]) -> Vec<Vec<u8>> {
+    let stored = StoredVault::<96, 32>::decode(image_contents(image).unwrap()).unwrap();
+    let data_key = stored.vault.unlock(b"ababxy").unwrap();
+    stored
+        .entries(&data_key)
+        .unwrap()
+        .map(|entry| Entry::parse(&entry.unwrap()).unwrap().name().to_vec())
+        .collect()
+}
+
+#[test]
+fn firmware_opens_the_image() {
+    let image = emit::image(&filled(), SEALING, 32, 4096).unwrap();
+    assert_eq!(4096, image.len());
+
+    // as the firmware would, with its sizes fixed at compile time
+    let vault = Vault::<96, 2>::decode(image_contents(&image).unwrap()).unwrap();
+    let data_key = vault.unlock(b"ababxy").unwrap();
+    let mut entries = vault.entries(&data_key).unwrap();
+    let first = entries.next().unwrap().unwrap();
+    let first = Entry::parse(&first).unwrap();
+    assert_eq!(b"ABCD", first.name());
+    assert_eq!(
+        Some((&b"url"[..], &b"https://example.com"[..])),
+        first.extras().next()
+    );
+    let second = entries.next().unwrap().unwrap();
+    assert_eq!(b"XYZ", Entry::parse(&second).unwrap().name());
+}
+
+#[test]
+fn changes_bump_the_version_and_keep_the_mac() {
+    let mut store = filled();
+    let data_key = store.unlock(b"ababxy").unwrap();
+    let mut records = store.records(&data_key).unwrap();
+    records.remove(0);
+    store.replace(&data_key, &records, SEALING).unwrap();
+    assert_eq!(3, store.vault.keyring.version);
+
+    // the remaining entry is resealed under its new index
+    let records = store.records(&data_key).unwrap();
+    assert_eq!(1, records.len());
+    assert_eq!("XYZ", records[0].name());
+    assert_eq!(0, store.vault.entries[0].context);
+
+    store.vault.entries[0].ciphertext[0] ^= 1;
+    assert!(store.records(&data_key).is_err());
+}
+
+#[test]
+fn code_can_be_changed() {
+    let mut store = filled();
+    assert!(store.change_code(KDF, b"xyxyab", b"bbbbbb", 6).is_err());
+    store.change_code(KDF, b"ababxy", b"xyxyab", 6).unwrap();
+
+    let data_key = store.unlock(b"xyxyab").unwrap();
+    assert_eq!(2, store.records(&data_key).unwrap().len());
+    assert!(store.unlock(b"ababxy").is_err());
+}
+
+#[test]
+fn codes_have_to_suit_the_buttons() {
+    let message = Err("the unlock code has to be 6 presses of a, b, x or y".to_string());
+    for code in [&b"ababx"[..], b"ababxyy", b"ABABXY", b"abab12"] {
+        assert_eq!(
+            message,
+            Store::create(KDF, Algorithm::DEFAULT, code, 6).map(|_| ())
+        );
+    }
+    assert!(Store::create(KDF, Algorithm::DEFAULT, b"abxy", 4).is_ok());
+
+    let mut store = filled();
+    assert_eq!(message, store.change_code(KDF, b"ababxy", b"abcdef", 6));
+    assert!(store.unlock(b"ababxy").is_ok());
+}
+
+#[test]
+fn entries_must_fit_the_capacity() {
+    let mut store = filled();
+    let data_key = store.unlock(b"ababxy").unwrap();
+    let records = [record("LONG", "", &"x".repeat(80))];
+    assert!(store.replace(&data_key, &records, SEALING).is_err());
+
+    // entries sealed for a larger capacity can't be emitted for a smaller one
+    store
+        .replace(
+            &data_key,
+            &records,
+            Sealing {
+                capacity: 128,
+                ..SEALING
+            },
+        )
+        .unwrap();
+    assert!(emit::image(&store, SEALING, 32, 4096).is_err());
+
+    let empty = Store::create(KDF, Algorithm::DEFAULT, b"ababxy", 6).unwrap();
+    assert!(emit::rust(&empty, SEALING, 4096, 6, 32, "vault.hwpw").is_err());
+    assert!(emit::image(&filled(), SEALING, 32, 256).is_err());
+}
+
+#[test]
+fn module_defines_the_firmware_constants() {
+    let store = filled();
+    let module = emit::rust(&store, SEALING, 4096, 6, 32, "vault.hwpw").unwrap();
+    assert!(module.contains("pub const CODE_LENGTH: usize = 6;\n"));
+    assert!(module.contains("pub const MAX_ENTRIES: usize = 32;\n"));
+    assert!(module.contains("pub const ENTRY_CAPACITY: usize = 96;\n"));
+    assert!(module.contains("    static IMAGE: [u8; 4096] = [\n"));
+
+    let image: Vec<u8> = module
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|word| word.strip_prefix("0x"))
+        .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+        .collect();
+    assert_eq!(emit::image(&store, SEALING, 32, 4096).unwrap(), image);
+}
+
+// the fixtures are laid out as the firmware is, with code and a vault image of two
+// entries, OLD1 and OLD2, but no more than that
+#[test]
+fn elf_vault_is_patched() {
+    let original = std::fs::read("testdata/firmware.elf").unwrap();
+    let section = patch::elf_section(&original, ".vault").unwrap();
+    assert_eq!(
+        vec![b"OLD1".to_vec(), b"OLD2".to_vec()],
+        names(&original[section.clone()])
+    );
+
+    let mut patched = original.clone();
+    patch::patch(&mut patched, &filled(), SEALING, 32, 0, 0).unwrap();
+    assert_eq!(section, patch::elf_section(&patched, ".vault").unwrap());
+    assert_eq!(
+        vec![b"ABCD".to_vec(), b"XYZ".to_vec()],
+        names(&patched[section.clone()])
+    );
+    assert_eq!(original[..section.start], patched[..section.start]);
+    assert_eq!(original[section.end..], patched[section.end..]);
+}
+
+#[test]
+fn malformed_elf_offsets_are_errors() {
+    let original = std::fs::read("testdata/firmware.elf").unwrap();
+    // the section headers' offset, then their size and the index of the names' header
+    for (at, value) in [
+        (0x20, &[0xff; 4][..]),
+        (0x2e, &[0xff; 2]),
+        (0x32, &[0xff; 2]),
+    ] {
+        let mut elf = original.clone();
+        elf[at..at + value.len()].copy_from_slice(value);
+        assert_eq!(
+            Err("the ELF file is malformed".into()),
+            patch::elf_section(&elf, ".vault")
+        );
+    }
+}
+
+#[test]
+fn uf2_vault_is_patched() {
+    let original = std::fs::read("testdata/firmware.uf2").unwrap();
+    let mut patched = original.clone();
+    patch::patch(&mut patched, &filled(), SEALING, 32, VAULT_ADDRESS, 4096).unwrap();
+
+    let blocks = uf2::blocks(&patched).unwrap();
+    let mut image = Vec::new();
+    for block in &blocks {
+        if block.address >= VAULT_ADDRESS {
+            image.extend_from_slice(&patched[block.payload.clone()]);
+        } else {
+            assert_eq!(
+                original[block.payload.clone()],
+                patched[block.payload.clone()]
+            );
+        }
+    }
+    assert_eq!(vec![b"ABCD".to_vec(), b"XYZ".to_vec()], names(&image));
+
+    // only payloads change, not the blocks' headers
+    let blocks = original
+        .chunks(uf2::BLOCK_LEN)
+        .zip(patched.chunks(uf2::BLOCK_LEN));
+    for (old, new) in blocks {
+        assert_eq!(old[..32], new[..32]);
+    }
+}
+
+#[test]
+fn patches_have_to_suit_the_firmware() {
+    let mut elf = std::fs::read("testdata/firmware.elf").unwrap();
+    assert_eq!(
+        Err(
+            "the vault has 2 entries, but the firmware only has room for 1; raise its \
+             `MAX_ENTRIES` and `--max-entries` to match"
+                .into()
+        ),
+        patch::patch(&mut elf, &filled(), SEALING, 1, 0, 0)
+    );
+
+    // the firmware loads however many entries there are, up to its MAX_ENTRIES
+    let mut store = filled();
+    let data_key = store.unlock(b"ababxy").unwrap();
+    let records = [record("ONE", "", "only")];
+    store.replace(&data_key, &records, SEALING).unwrap();
+    patch::patch(&mut elf, &store, SEALING, 32, 0, 0).unwrap();
+    let section = patch::elf_section(&elf, ".vault").unwrap();
+    assert_eq!(vec![b"ONE".to_vec()], names(&elf[section]));
+
+    let mut uf2 = std::fs::read("testdata/firmware.uf2").unwrap();
+    let moved = VAULT_ADDRESS - 0x1000;
+    assert!(patch::patch(&mut uf2, &filled(), SEALING, 32, moved, 4096).is_err());
+    assert!(patch::patch(&mut uf2, &filled(), SEALING, 32, VAULT_ADDRESS, 8192).is_err());
+    assert!(patch::patch(&mut [0; 1024], &filled(), SEALING, 32, 0, 0).is_err());
+    assert_eq!(std::fs::read("testdata/firmware.uf2").unwrap(), uf2);
+}
+
+#[test]
+fn entries_are_found_by_name_or_number() {
+    let records = [
+        record("ABCD", "", "a"),
+        record("XYZ", "", "b"),
+        record("ABCD", "", "c"),
+    ];
+    assert_eq!(Ok(1), find(&records, "XYZ"));
+    assert_eq!(Ok(2), find(&records, "#3"));
+    assert!(find(&records, "ABCD").is_err());
+    assert!(find(&records, "#0").is_err());
+    assert!(find(&records, "#4").is_err());
+    assert!(find(&records, "Mail").is_err());
+}
+
+#[test]
+fn generated_passwords_can_be_typed() {
+    let password = generate::password(64, true).unwrap();
+    assert_eq!(64, password.len());
+    assert!(generate::check_typable("the password", &password).is_ok());
+
+    let password = generate::password(64, false).unwrap();
+    assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    assert!(generate::check_typable("the password", "caf\u{e9}").is_err());
+    assert!(generate::check_typable("the password", "tab\there").is_err());
+}
+
+#[test]
+fn uf2_holds_only_the_vault() {
+    let image = emit::image(&filled(), SEALING, 32, 4096).unwrap();
+    let file = uf2::write(VAULT_ADDRESS, &image).unwrap();
+    assert_eq!(16 * uf2::BLOCK_LEN, file.len());
+
+    let blocks = uf2::blocks(&file).unwrap();
+    let mut written = Vec::new();
+    for (i, block) in (0..).zip(&blocks) {
+        assert_eq!(uf2::FAMILY_ID_PRESENT, block.flags);
+        assert_eq!(uf2::RP2040_FAMILY_ID, block.family);
+        assert_eq!(VAULT_ADDRESS + i * 256, block.address);
+        written.extend_from_slice(&file[block.payload.clone()]);
+    }
+    assert_eq!(image, written);
+    assert_eq!(vec![b"ABCD".to_vec(), b"XYZ".to_vec()], names(&written));
+
+    // blocks are numbered in order, out of however many there are
+    let mut renumbered = file.clone();
+    renumbered[uf2::BLOCK_LEN + 20] = 2;
+    assert!(uf2::blocks(&renumbered).is_err());
+    assert!(uf2::blocks(&file[..15 * uf2::BLOCK_LEN]).is_err());
+
+    // erasing a sector that's only partly written would take code with it
+    assert!(uf2::write(VAULT_ADDRESS + 256, &image).is_err());
+    assert!(uf2::write(VAULT_ADDRESS, &image[..2048]).is_err());
+
+    // or outside flash, including past the end of the address space
+    assert!(uf2::write(0x2000_0000, &image).is_err());
+    assert!(uf2::write(0x0fff_f000, &image).is_err());
+    assert!(uf2::write(0x10ff_f000, &[0; 8192]).is_err());
+    assert!(uf2::write(0xffff_f000, &[0; 8192]).is_err());
+    assert!(uf2::write(0x10ff_f000, &image).is_ok());
+}
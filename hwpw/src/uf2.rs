@@ -0,0 +1,137 @@
+//! UF2, the format the RP2040's boot ROM takes over USB mass storage: 512 byte
+//! blocks, each carrying a payload to write at an address in flash. See
+//! <https://github.com/microsoft/uf2>. All integers are little-endian.
+
+pub(crate) const BLOCK_LEN: usize = 512;
+const MAGIC_START: [u32; 2] = [0x0a32_4655, 0x9e5d_5157];
+const MAGIC_END: u32 = 0x0ab1_6f30;
+const PAYLOAD_OFFSET: usize = 32;
+const MAX_PAYLOAD_LEN: usize = 476;
+
+/// The boot ROM writes flash a page at a time, so each block carries one page...
+const PAGE_LEN: usize = 256;
+/// ...but erases it a sector at a time, which anything written has to fill.
+const SECTOR_LEN: usize = 4096;
+/// Where the RP2040 maps flash, which can be up to 16 MiB.
+const FLASH: std::ops::Range<u32> = 0x1000_0000..0x1100_0000;
+
+/// Blocks with this flag aren't written to flash.
+pub(crate) const NOT_MAIN_FLASH: u32 = 0x1;
+/// Blocks with this flag name their chip family where the file size would otherwise be.
+pub(crate) const FAMILY_ID_PRESENT: u32 = 0x2000;
+pub(crate) const RP2040_FAMILY_ID: u32 = 0xe48b_ff56;
+
+/// A block's header, with the position of its payload in the file.
+pub(crate) struct Block {
+    pub(crate) flags: u32,
+    pub(crate) address: u32,
+    /// The family ID, or the file size if `FAMILY_ID_PRESENT` isn't set.
+    pub(crate) family: u32,
+    pub(crate) payload: std::ops::Range<usize>,
+}
+
+impl Block {
+    /// Whether the block's payload is written to flash at its address.
+    pub(crate) fn in_flash(&self) -> bool {
+        self.flags & NOT_MAIN_FLASH == 0
+    }
+
+    /// Whether the block is for the RP2040, or doesn't say.
+    pub(crate) fn for_rp2040(&self) -> bool {
+        self.flags & FAMILY_ID_PRESENT == 0 || self.family == RP2040_FAMILY_ID
+    }
+}
+
+pub(crate) fn is_uf2(bytes: &[u8]) -> bool {
+    bytes.len() >= 8 && word(bytes, 0) == MAGIC_START[0] && word(bytes, 4) == MAGIC_START[1]
+}
+
+pub(crate) fn blocks(bytes: &[u8]) -> Result<Vec<Block>, String> {
+    if !bytes.len().is_multiple_of(BLOCK_LEN) {
+        return Err(format!(
+            "a UF2 file is made of {BLOCK_LEN} byte blocks, but this one is {} bytes",
+            bytes.len()
+        ));
+    }
+
+    bytes
+        .chunks(BLOCK_LEN)
+        .enumerate()
+        .map(|(i, block)| {
+            if word(block, 0) != MAGIC_START[0]
+                || word(block, 4) != MAGIC_START[1]
+                || word(block, BLOCK_LEN - 4) != MAGIC_END
+            {
+                return Err(format!("UF2 block {i} has the wrong magic numbers"));
+            }
+            let len = word(block, 16) as usize;
+            if len > MAX_PAYLOAD_LEN {
+                return Err(format!("UF2 block {i} has a payload of {len} bytes"));
+            }
+            let (number, count) = (word(block, 20), word(block, 24));
+            if (number as usize, count as usize) != (i, bytes.len() / BLOCK_LEN) {
+                return Err(format!("UF2 block {i} is numbered {number} of {count}"));
+            }
+            let start = i * BLOCK_LEN + PAYLOAD_OFFSET;
+            Ok(Block {
+                flags: word(block, 8),
+                address: word(block, 12),
+                family: word(block, 28),
+                payload: start..start + len,
+            })
+        })
+        .collect()
+}
+
+/// A UF2 file which writes `data` to flash at `address` and nothing else, as
+/// `elf2uf2-rs` would for the RP2040.
+pub(crate) fn write(address: u32, data: &[u8]) -> Result<Vec<u8>, String> {
+    if !(address as usize).is_multiple_of(SECTOR_LEN) || !data.len().is_multiple_of(SECTOR_LEN) {
+        return Err(format!(
+            "the boot ROM erases flash in {SECTOR_LEN} byte sectors, so a UF2 file can only \
+             replace whole ones, but this one would be {} bytes at {address:#010x}",
+            data.len()
+        ));
+    }
+    let end = u32::try_from(data.len())
+        .ok()
+        .and_then(|len| address.checked_add(len));
+    if address < FLASH.start || end.is_none_or(|end| end > FLASH.end) {
+        return Err(format!(
+            "{} bytes at {address:#010x} isn't all in the RP2040's flash, at {:#010x} to \
+             {:#010x}",
+            data.len(),
+            FLASH.start,
+            FLASH.end
+        ));
+    }
+
+    let count = (data.len() / PAGE_LEN) as u32;
+    let mut uf2 = Vec::with_capacity(count as usize * BLOCK_LEN);
+    for (number, page) in (0u32..).zip(data.chunks(PAGE_LEN)) {
+        let header = [
+            MAGIC_START[0],
+            MAGIC_START[1],
+            FAMILY_ID_PRESENT,
+            number
+                .checked_mul(PAGE_LEN as u32)
+                .and_then(|offset| address.checked_add(offset))
+                .expect("the pages are all in flash"),
+            PAGE_LEN as u32,
+            number,
+            count,
+            RP2040_FAMILY_ID,
+        ];
+        for word in header {
+            uf2.extend_from_slice(&word.to_le_bytes());
+        }
+        uf2.extend_from_slice(page);
+        uf2.resize(uf2.len() + MAX_PAYLOAD_LEN - PAGE_LEN, 0);
+        uf2.extend_from_slice(&MAGIC_END.to_le_bytes());
+    }
+    Ok(uf2)
+}
+
+fn word(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
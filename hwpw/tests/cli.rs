@@ -0,0 +1,100 @@
+// runs the binary as a script would, with codes and passwords piped in a line at a time
+use std::{
+    io::{ErrorKind, Write},
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+fn hwpw(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hwpw"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // a command which fails early may exit before it reads its input
+    match child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => panic!("{e}"),
+        _ => {}
+    }
+    child.wait_with_output().unwrap()
+}
+
+fn succeeds(dir: &Path, args: &[&str], stdin: &str) -> String {
+    let output = hwpw(dir, args, stdin);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn vault_lifecycle() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+
+    let output = hwpw(dir, &["create"], "abab12\n");
+    assert_eq!(
+        "hwpw: the unlock code has to be 6 presses of a, b, x or y\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    succeeds(dir, &["create", "--rounds", "1"], "ababxy\n");
+    succeeds(
+        dir,
+        &["add", "ABCD", "-u", "abcd_user", "--extra", "url=x"],
+        "ababxy\nsw0rd*f1sh\n",
+    );
+    succeeds(dir, &["add", "XYZ", "--generate"], "ababxy\n");
+    succeeds(dir, &["add", "Mail"], "ababxy\npersonal\n");
+    succeeds(dir, &["edit", "XYZ", "-u", "xyz-user"], "ababxy\n");
+    succeeds(dir, &["remove", "Mail"], "ababxy\n");
+    succeeds(dir, &["code"], "ababxy\nxyxyab\n");
+
+    let list = succeeds(dir, &["list"], "xyxyab\n");
+    assert_eq!("  1  ABCD  abcd_user\n  2  XYZ   xyz-user\n", list);
+
+    let output = hwpw(dir, &["list"], "ababxy\n");
+    assert!(!output.status.success());
+    assert_eq!(
+        "hwpw: wrong unlock code\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = hwpw(dir, &["create"], "ababxy\n");
+    assert!(!output.status.success());
+
+    let module = succeeds(dir, &["rust"], "");
+    assert!(module.contains("pub const MAX_ENTRIES: usize = 32;"));
+    succeeds(dir, &["image", "-o", "vault.bin"], "");
+    let image = std::fs::read(dir.join("vault.bin")).unwrap();
+    assert_eq!(4096, image.len());
+    assert_eq!(b"hwpw", &image[4..8]);
+
+    let firmware = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/firmware.uf2");
+    succeeds(dir, &["patch", firmware, "-o", "firmware.uf2"], "");
+    let patched = std::fs::read(dir.join("firmware.uf2")).unwrap();
+    assert_eq!(std::fs::read(firmware).unwrap().len(), patched.len());
+
+    succeeds(dir, &["uf2", "-o", "vault.uf2"], "");
+    assert_eq!(
+        16 * 512,
+        std::fs::read(dir.join("vault.uf2")).unwrap().len()
+    );
+    let output = hwpw(
+        dir,
+        &["uf2", "-o", "vault.uf2", "--address", "0x101ff100"],
+        "",
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn generated_password_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let password = succeeds(dir.path(), &["generate", "--length", "32"], "");
+    assert_eq!(33, password.len());
+}
@@ -0,0 +1,13 @@
+//! Declares what the firmware's `secrets.rs` reads, as the firmware's own build script
+//! does, since `hwsim` builds it too, and has the macros find its files in the
+//! firmware's directory rather than this one.
+
+#[path = "../firmware/secrets_inputs.rs"]
+mod secrets_inputs;
+
+use std::path::Path;
+
+fn main() {
+    let firmware = Path::new(env!("CARGO_MANIFEST_DIR")).join("../firmware");
+    secrets_inputs::track(&firmware.join("src/secrets.rs"), &firmware);
+}
@@ -0,0 +1,155 @@
+//! The device around the app: its LCD, kept as `lcd.rs` keeps it, and the text its
+//! USB keyboard types, as `usb.rs` types it.
+
+use etpwtc::StoredVault;
+use etpwtc_app::{App, Button, Command, LcdMessage, UsbMessage, VaultProblem};
+use std::str::from_utf8;
+
+pub(crate) struct Device<const N: usize, const MAX: usize, const CODE_LENGTH: usize> {
+    /// The app, unless there's no vault, in which case the buttons do nothing.
+    app: Option<App<N, MAX, CODE_LENGTH>>,
+    pub(crate) screen: Screen,
+    /// The lowest vault version the app opens, as the firmware keeps it in SETTINGS.
+    pub(crate) minimum_version: u32,
+}
+
+impl<const N: usize, const MAX: usize, const CODE_LENGTH: usize> Device<N, MAX, CODE_LENGTH> {
+    /// A device with the vault it loaded, or the reason it couldn't.
+    pub(crate) fn new(
+        vault: Result<StoredVault<N, MAX>, VaultProblem>,
+        minimum_version: u32,
+    ) -> Self {
+        let mut screen = Screen::new();
+        if let Err(problem) = vault {
+            screen.show(LcdMessage::NoVault(problem));
+        }
+        Device {
+            app: vault.ok().map(|vault| App::new(vault, minimum_version)),
+            screen,
+            minimum_version,
+        }
+    }
+
+    /// Presses `button`, returning whatever the keyboard types.
+    pub(crate) fn press(&mut self, button: Button) -> Option<String> {
+        let mut typed = None;
+        for command in self.app.as_mut()?.press(button) {
+            match command {
+                Command::Lcd(message) => self.screen.show(message),
+                Command::Usb(message) => typed = Some(typed_text(&message)),
+                Command::KeepVersion(version) => self.minimum_version = version,
+            }
+        }
+        typed
+    }
+
+    /// Lets the backlight time out, as it would if no button were pressed for a while.
+    pub(crate) fn wait(&mut self) {
+        self.screen.snooze();
+    }
+}
+
+/// The text the keyboard types for `message`.
+pub(crate) fn typed_text<const N: usize>(message: &UsbMessage<N>) -> String {
+    let mut text = String::new();
+    match message {
+        UsbMessage::Credentials { username, password } => {
+            type_str(&mut text, username);
+            text.push('\t');
+            type_str(&mut text, password);
+        }
+        UsbMessage::Password { password } => type_str(&mut text, password),
+    }
+    text.push('\n');
+    text
+}
+
+/// The keyboard has a key for every printable ASCII character, and types anything
+/// else as `?`.
+fn type_str(text: &mut String, bytes: &[u8]) {
+    text.extend(bytes.iter().map(|b| match b {
+        b' '..=b'~' => *b as char,
+        _ => '?',
+    }));
+}
+
+/// What the LCD shows. The backlight goes off when the screen is told to snooze,
+/// rather than after 4 seconds.
+#[derive(Debug)]
+pub(crate) struct Screen {
+    pub(crate) backlight: bool,
+    /// Whether the backlight goes off next time the screen snoozes.
+    snoozing: bool,
+    name: [u8; 4],
+    unlocked: bool,
+    no_vault: Option<VaultProblem>,
+}
+
+/// The width of the text drawn inside the screen's border.
+const WIDTH: usize = 24;
+
+impl Screen {
+    fn new() -> Self {
+        Screen {
+            backlight: true,
+            snoozing: false,
+            name: *b"INIT",
+            unlocked: false,
+            no_vault: None,
+        }
+    }
+
+    fn show(&mut self, message: LcdMessage) {
+        match message {
+            LcdMessage::SetName(name) => self.name = name,
+            LcdMessage::Lock => {
+                self.unlocked = false;
+                self.snoozing = true;
+            }
+            LcdMessage::Unlock => {
+                self.unlocked = true;
+                self.snoozing = false;
+                self.backlight = true;
+            }
+            LcdMessage::NoVault(problem) => {
+                self.no_vault = Some(problem);
+                self.backlight = true;
+            }
+            LcdMessage::Wake => {
+                self.backlight = true;
+                self.snoozing = true;
+            }
+        }
+    }
+
+    fn snooze(&mut self) {
+        if self.snoozing {
+            self.snoozing = false;
+            self.backlight = false;
+        }
+    }
+
+    /// The screen drawn in text, with the buttons' icons as labels beside them.
+    pub(crate) fn render(&self) -> String {
+        let rows = if !self.backlight {
+            [String::new(), "(backlight off)".into(), String::new()]
+        } else if self.unlocked {
+            [
+                format!("A lock{:>1$}", "login X", WIDTH - 6),
+                from_utf8(&self.name).unwrap_or("????").into(),
+                format!("B next{:>1$}", "password Y", WIDTH - 6),
+            ]
+        } else if let Some(problem) = self.no_vault {
+            [String::new(), "NO VAULT".into(), problem.label().into()]
+        } else {
+            [String::new(), "LOCKED".into(), String::new()]
+        };
+
+        let border = format!("+{}+\n", "-".repeat(WIDTH + 2));
+        let mut screen = border.clone();
+        for row in rows {
+            screen += &format!("| {row:^WIDTH$} |\n");
+        }
+        screen + &border
+    }
+}
@@ -0,0 +1,194 @@
+//! `hwsim` runs the password keyboard's app on the host, drawing its LCD as text and
+//! printing what its USB keyboard would type, so that vault and UI changes can be
+//! tried without flashing a Pico.
+//!
+//! It's built with the firmware's `secrets.rs`, for the same sizes of vault, and starts
+//! with the vault image built into it, or one `hwpw image` wrote, which it loads as the
+//! firmware loads its VAULT region.
+//! Button presses are read a line at a time, from stdin or a script, and can be
+//! recorded to a script to replay, so that a session's transcript can be compared
+//! with an earlier one's.
+
+mod device;
+#[path = "../../firmware/src/secrets.rs"]
+mod secrets;
+#[cfg(test)]
+mod tests;
+
+use clap::Parser;
+use device::Device;
+use etpwtc::{load, Storage, StoredVault};
+use etpwtc_app::{Button, VaultProblem};
+use secrets::{CODE_LENGTH, ENTRY_CAPACITY, MAX_ENTRIES};
+use std::{
+    convert::Infallible,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Runs the password keyboard's app on the terminal")]
+struct Cli {
+    /// A vault image to load, as if from the VAULT region of flash. Without one, the
+    /// device has the vault the firmware was built with, if any.
+    #[arg(long)]
+    image: Option<PathBuf>,
+
+    /// Reads button presses from a script rather than stdin, stopping at any mistake.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Writes the button presses to a script, to replay later.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// The lowest vault version to open, as if kept in the SETTINGS region by an
+    /// earlier vault. Opening a newer one prints its version, to pass here next time.
+    #[arg(long, default_value_t = 0)]
+    minimum_version: u32,
+}
+
+const HELP: &str = "press a, b, x or y and enter; several can go on a line, and . lets the \
+                    backlight time out";
+
+/// Something to do to the device, written in scripts as the button's letter or `.`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Press(Button),
+    Wait,
+}
+
+impl Step {
+    fn letter(self) -> char {
+        match self {
+            Step::Press(button) => button.code() as char,
+            Step::Wait => '.',
+        }
+    }
+}
+
+/// The steps in a line of input, ignoring whitespace and anything after `#`.
+fn steps(line: &str) -> Result<Vec<Step>, String> {
+    let line = line.split('#').next().unwrap_or_default();
+    line.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c.to_ascii_lowercase() {
+            'a' => Ok(Step::Press(Button::A)),
+            'b' => Ok(Step::Press(Button::B)),
+            'x' => Ok(Step::Press(Button::X)),
+            'y' => Ok(Step::Press(Button::Y)),
+            '.' => Ok(Step::Wait),
+            _ => Err(format!("there's no `{c}` button; {HELP}")),
+        })
+        .collect()
+}
+
+/// A vault image read from a file, standing in for the VAULT region.
+struct ImageFile(Vec<u8>);
+
+impl Storage for ImageFile {
+    type Error = Infallible;
+
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Infallible> {
+        bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+        Ok(())
+    }
+}
+
+/// Loads the vault image at `path`, or else the one in `secrets.rs`. Like the
+/// firmware, this carries on without a vault if it can't be used, after saying why.
+fn load_image<const N: usize, const MAX: usize>(
+    path: Option<&Path>,
+) -> Result<Result<StoredVault<N, MAX>, VaultProblem>, String> {
+    let (image, name) = match path {
+        Some(path) => (
+            fs::read(path).map_err(|e| format!("can't read `{}`: {e}", path.display()))?,
+            format!("`{}`", path.display()),
+        ),
+        // a firmware built without one leaves the region as it was, which a fresh
+        // device has erased
+        None if secrets::VAULT_IMAGE.is_empty() => return Ok(Err(VaultProblem::Empty)),
+        None => (secrets::VAULT_IMAGE.to_vec(), "its `VAULT_IMAGE`".into()),
+    };
+    let mut buffer = vec![0; image.len()];
+    Ok(load(&mut ImageFile(image), &mut buffer).map_err(|e| {
+        eprintln!("hwsim: the firmware couldn't use {name}, so it has no vault: {e:?}");
+        VaultProblem::from(&e)
+    }))
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("hwsim: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let vault = load_image(cli.image.as_deref())?;
+    let mut device =
+        Device::<ENTRY_CAPACITY, MAX_ENTRIES, CODE_LENGTH>::new(vault, cli.minimum_version);
+
+    let input: Box<dyn BufRead> = match &cli.replay {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("can't read `{}`: {e}", path.display()))?,
+        )),
+        None => {
+            if io::stdin().is_terminal() {
+                eprintln!("{HELP}");
+            }
+            Box::new(io::stdin().lock())
+        }
+    };
+    let mut record = match &cli.record {
+        Some(path) => {
+            Some(File::create(path).map_err(|e| format!("can't write `{}`: {e}", path.display()))?)
+        }
+        None => None,
+    };
+
+    print!("{}", device.screen.render());
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("can't read the button presses: {e}"))?;
+        let steps = match steps(&line) {
+            Ok(steps) if steps.is_empty() => continue,
+            Ok(steps) => steps,
+            Err(e) if cli.replay.is_some() => return Err(format!("line {}: {e}", i + 1)),
+            Err(e) => {
+                eprintln!("hwsim: {e}");
+                continue;
+            }
+        };
+
+        let script: String = steps.iter().map(|step| step.letter()).collect();
+        if let Some(record) = &mut record {
+            writeln!(record, "{script}").map_err(|e| format!("can't record the presses: {e}"))?;
+        }
+        println!("> {script}");
+        for step in steps {
+            match step {
+                Step::Press(button) => {
+                    let minimum_version = device.minimum_version;
+                    if let Some(typed) = device.press(button) {
+                        println!("typed {typed:?}");
+                    }
+                    if device.minimum_version != minimum_version {
+                        println!("kept version {} as the minimum", device.minimum_version);
+                    }
+                }
+                Step::Wait => device.wait(),
+            }
+        }
+        print!("{}", device.screen.render());
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::{
+    device::{typed_text, Device, Screen},
+    steps, Step,
+};
+use etpwtc::{image_contents, vault, Plaintext, StoredVault};
+use etpwtc_app::{Button::*, UsbMessage, VaultProblem};
+
+static IMAGE: &[u8] = vault!(
+    codes = [b"xyxyab"],
+    entries = [
+        { name: b"one", username: b"user1", password: b"pass1" },
+        { name: b" XYZ", username: b"xyz-user", password: b"p@ss 2" },
+    ],
+    rounds = 1,
+    capacity = 64,
+    image = 1024,
+);
+
+fn stored() -> StoredVault<64, 4> {
+    StoredVault::decode(image_contents(IMAGE).unwrap()).unwrap()
+}
+
+fn device() -> Device<64, 4, 6> {
+    Device::new(Ok(stored()), 1)
+}
+
+fn rows(screen: &Screen) -> Vec<String> {
+    screen
+        .render()
+        .lines()
+        .map(|row| row.trim_matches(['|', '+', '-', ' ']).to_string())
+        .collect()
+}
+
+fn unlock(device: &mut Device<64, 4, 6>) {
+    for button in [X, Y, X, Y, A, B] {
+        assert_eq!(None, device.press(button));
+    }
+}
+
+#[test]
+fn screen_follows_the_app() {
+    let mut device = device();
+    assert_eq!(["", "", "LOCKED", "", ""], rows(&device.screen)[..]);
+
+    unlock(&mut device);
+    let screen = device.screen.render();
+    assert_eq!(
+        "+--------------------------+\n\
+         | A lock           login X |\n\
+         |           one            |\n\
+         | B next        password Y |\n\
+         +--------------------------+\n",
+        screen
+    );
+    device.press(B);
+    assert_eq!("XYZ", rows(&device.screen)[2]);
+
+    device.press(A);
+    assert_eq!("LOCKED", rows(&device.screen)[2]);
+    device.wait();
+    assert!(!device.screen.backlight);
+    assert_eq!("(backlight off)", rows(&device.screen)[2]);
+
+    // a wrong code wakes the screen, and the right one unlocks it as it was
+    device.press(Y);
+    assert_eq!("LOCKED", rows(&device.screen)[2]);
+    unlock(&mut device);
+    assert_eq!("XYZ", rows(&device.screen)[2]);
+    device.wait();
+    assert!(device.screen.backlight);
+}
+
+#[test]
+fn typed_text_is_what_the_keyboard_types() {
+    let mut device = device();
+    unlock(&mut device);
+    assert_eq!(Some("user1\tpass1\n".into()), device.press(X));
+    assert_eq!(Some("pass1\n".into()), device.press(Y));
+
+    device.press(B);
+    assert_eq!(Some("p@ss 2\n".into()), device.press(Y));
+
+    // there are no keys for anything but printable ASCII
+    let password = Plaintext::<8>::from_slice(b"caf\xc3\xa9\x01").unwrap();
+    assert_eq!("caf???\n", typed_text(&UsbMessage::Password { password }));
+}
+
+#[test]
+fn no_vault_ignores_buttons() {
+    let mut device = Device::<64, 4, 6>::new(Err(VaultProblem::BadImage), 0);
+    assert_eq!("NO VAULT", rows(&device.screen)[2]);
+    assert_eq!("BAD IMAGE", rows(&device.screen)[3]);
+    for button in [X, Y, X, Y, A, B, X] {
+        assert_eq!(None, device.press(button));
+    }
+    assert_eq!("NO VAULT", rows(&device.screen)[2]);
+}
+
+#[test]
+fn damaged_vault_is_no_vault_once_opened() {
+    let mut stored = stored();
+    stored.entries[1].ciphertext[0] ^= 1;
+    let mut device = Device::new(Ok(stored), 1);
+    assert_eq!("LOCKED", rows(&device.screen)[2]);
+    unlock(&mut device);
+    assert_eq!("NO VAULT", rows(&device.screen)[2]);
+    assert_eq!("DAMAGED", rows(&device.screen)[3]);
+    assert_eq!(None, device.press(X));
+}
+
+#[test]
+fn versions_are_kept_and_checked() {
+    let mut device = Device::<64, 4, 6>::new(Ok(stored()), 0);
+    unlock(&mut device);
+    assert_eq!(1, device.minimum_version);
+    assert_eq!("one", rows(&device.screen)[2]);
+
+    let mut device = Device::<64, 4, 6>::new(Ok(stored()), 2);
+    unlock(&mut device);
+    assert_eq!(2, device.minimum_version);
+    assert_eq!("NO VAULT", rows(&device.screen)[2]);
+    assert_eq!("ROLLBACK", rows(&device.screen)[3]);
+}
+
+#[test]
+fn scripts_are_buttons_and_waits() {
+    assert_eq!(
+        Ok(vec![
+            Step::Press(A),
+            Step::Press(B),
+            Step::Wait,
+            Step::Press(Y)
+        ]),
+        steps(" a B . y # then x")
+    );
+    assert_eq!(Ok(vec![]), steps("# nothing"));
+    assert!(steps("abc").is_err());
+}
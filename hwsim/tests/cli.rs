@@ -0,0 +1,72 @@
+// runs the binary as a script would, with button presses piped in a line at a time
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+fn hwsim(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hwsim"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn succeeds(dir: &Path, args: &[&str], stdin: &str) -> String {
+    let output = hwsim(dir, args, stdin);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn replays_match_recordings() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+
+    // with whichever vault the firmware was built with
+    let recorded = succeeds(dir, &["--record", "script"], "ab\nwhat\n\nx . y # and y\n");
+    assert!(recorded.starts_with("+---"));
+    assert_eq!(
+        "ab\nx.y\n",
+        std::fs::read_to_string(dir.join("script")).unwrap()
+    );
+
+    let replayed = succeeds(dir, &["--replay", "script"], "");
+    assert_eq!(recorded, replayed);
+
+    std::fs::write(dir.join("script"), "ab\nwhat\n").unwrap();
+    let output = hwsim(dir, &["--replay", "script"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("hwsim: line 2: "));
+}
+
+#[test]
+fn unusable_images_leave_no_vault() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+
+    std::fs::write(dir.join("vault.bin"), [0xff; 4096]).unwrap();
+    let output = hwsim(dir, &["--image", "vault.bin"], "xyxyab\n");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("NO VAULT"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("EMPTY"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Empty"));
+
+    let output = hwsim(dir, &["--image", "missing.bin"], "");
+    assert!(!output.status.success());
+}